@@ -5,7 +5,9 @@
 
 pub mod articles;
 pub mod config;
+pub mod deploys;
 pub mod err;
 pub mod folders;
+pub mod locale;
 pub mod pictures;
 pub mod sync;
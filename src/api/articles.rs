@@ -27,7 +27,7 @@ impl ArticleInfo {
 }
 
 /// full article including contents
-#[derive(Default, Serialize, Clone, Debug)]
+#[derive(Default, Serialize, Deserialize, Clone, Debug)]
 pub struct Article {
     pub title: String,
     pub date: String,
@@ -53,3 +53,19 @@ pub struct ArticleYaml {
     pub password: Option<String>,
     pub hint: Option<String>
 }
+
+/// a local picture referred to in an article, and the url replacing it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArticlePicture {
+    pub path: PathBuf,
+    pub modified: u64, // Unix Timestamp in millis
+    pub url: String,
+}
+
+/// an article processed before, reused while its source and pictures are unchanged
+pub struct CachedArticle {
+    pub path: PathBuf,
+    pub hash: String,
+    pub article: Article,
+    pub pictures: Vec<ArticlePicture>,
+}
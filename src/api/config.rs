@@ -1,9 +1,96 @@
 use crate::api::err;
+use crate::api::folders::WatchMode;
+use crate::api::locale::{self, Translations};
 
 use log::info;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde_yaml::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// an entry of the navigation menu
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NavEntry {
+    /// a translation key, or the text itself
+    pub label: String,
+    pub url: String,
+    /// the entry is active on pages whose path starts with any of these
+    /// only the page of url itself if empty
+    #[serde(default)]
+    pub active: Vec<String>,
+}
+
+/// where the site and the pictures are deployed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum DeployConfig {
+    /// copying the changed files with scp, stale files are removed with ssh
+    Scp {
+        server: String,
+        web_path: String,
+        pic_path: String,
+    },
+    /// copying the changed files with rsync, into a local folder if server is not set
+    Rsync {
+        server: Option<String>,
+        web_path: String,
+        pic_path: String,
+    },
+    /// copying into local folders
+    Local {
+        web_path: PathBuf,
+        pic_path: PathBuf,
+    },
+    /// committing into a clone of the remote and pushing it, the remote may be a local bare repo
+    Git {
+        remote: String,
+        #[serde(default = "default_branch")]
+        branch: String,
+        /// the folder of pictures inside the repo
+        #[serde(default = "default_git_pic_path")]
+        pic_path: String,
+        /// the clone, next to the output folder by default
+        work_dir: Option<PathBuf>,
+    },
+}
+
+fn default_branch() -> String {
+    String::from("main")
+}
+
+fn default_git_pic_path() -> String {
+    String::from("pic")
+}
+
+impl DeployConfig {
+    /// the name of the method, as in the config
+    pub fn method(&self) -> &'static str {
+        match self {
+            DeployConfig::Scp { .. } => "scp",
+            DeployConfig::Rsync { .. } => "rsync",
+            DeployConfig::Local { .. } => "local",
+            DeployConfig::Git { .. } => "git",
+        }
+    }
+
+    /// where the site is deployed, the deploys and releases are recorded per target
+    pub fn target(&self) -> String {
+        match self {
+            DeployConfig::Scp {
+                server, web_path, ..
+            } => format!("scp:{}:{}", server, web_path),
+            DeployConfig::Rsync {
+                server: Some(server),
+                web_path,
+                ..
+            } => format!("rsync:{}:{}", server, web_path),
+            DeployConfig::Rsync { web_path, .. } => format!("rsync:{}", web_path),
+            DeployConfig::Local { web_path, .. } => format!("local:{}", web_path.display()),
+            DeployConfig::Git { remote, branch, .. } => format!("git:{}#{}", remote, branch),
+        }
+    }
+}
 
 /// The global Config Struct
 #[derive(Debug, Deserialize, Serialize)]
@@ -20,29 +107,520 @@ pub struct GlobalConfig {
     pub pic_compress_threshold: u64,
     pub pic_replace_prefix: String,
 
+    /// used when deploy is not set
+    #[serde(default)]
     pub scp_server: String,
+    #[serde(default)]
     pub scp_pic_path: String,
+    #[serde(default)]
     pub scp_web_path: String,
+    pub deploy: Option<DeployConfig>,
 
     pub deploy_auto: bool,
     pub deploy_interval: Option<u64>,
+    /// removing the files no longer generated from the site when deploying
+    #[serde(default = "default_deploy_delete")]
+    pub deploy_delete: bool,
+    /// how many releases are kept on the deploy target, the live one included
+    #[serde(default = "default_deploy_keep_releases")]
+    pub deploy_keep_releases: usize,
 
     pub iv: Option<String>,
+
+    /// relative paths below are resolved against the folder of the config file
+    #[serde(default = "default_db_path")]
+    pub db_path: PathBuf,
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
+    #[serde(default = "default_template_dir")]
+    pub template_dir: PathBuf,
+    /// folders copied into the output folder as they are, such as css
+    #[serde(default = "default_static_dirs")]
+    pub static_dirs: Vec<PathBuf>,
+
+    /// the language of the ui strings, such as zh or en
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    /// folder of the translation files named <locale>.yaml, overriding the builtin ones
+    #[serde(default = "default_locale_dir")]
+    pub locale_dir: PathBuf,
+    #[serde(skip)]
+    pub translations: Translations,
+
+    /// the navigation menu in the header, in order
+    #[serde(default = "default_nav")]
+    pub nav: Vec<NavEntry>,
+
+    /// milliseconds a changed file should stay untouched before it's handled
+    /// the events of saving a file once are handled together
+    #[serde(default = "default_watch_debounce")]
+    pub watch_debounce: u64,
+    /// how the folders are watched, unless set for a folder
+    #[serde(default = "default_watch_mode")]
+    pub watch_mode: WatchMode,
+    /// seconds between the checks of a polled folder
+    #[serde(default = "default_watch_poll_interval")]
+    pub watch_poll_interval: u64,
+    /// seconds between the full scans of each folder, in case events are missed, 0 to disable
+    #[serde(default = "default_watch_rescan_interval")]
+    pub watch_rescan_interval: u64,
+
+    /// the files the config is read from, watched for hot reloading
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+    /// rendering for the preview server, into its own folder next to output_dir
+    #[serde(skip)]
+    pub preview: bool,
+}
+
+fn default_deploy_delete() -> bool {
+    true
+}
+
+fn default_deploy_keep_releases() -> usize {
+    5
+}
+
+fn default_db_path() -> PathBuf {
+    PathBuf::from("arce.db")
+}
+
+fn default_output_dir() -> PathBuf {
+    PathBuf::from("public")
+}
+
+fn default_template_dir() -> PathBuf {
+    PathBuf::from("template")
+}
+
+fn default_static_dirs() -> Vec<PathBuf> {
+    vec![PathBuf::from("css")]
+}
+
+fn default_locale() -> String {
+    String::from(locale::DEFAULT_LOCALE)
+}
+
+fn default_locale_dir() -> PathBuf {
+    PathBuf::from("locale")
+}
+
+fn default_watch_debounce() -> u64 {
+    500
+}
+
+fn default_watch_mode() -> WatchMode {
+    WatchMode::Native
+}
+
+fn default_watch_poll_interval() -> u64 {
+    5
+}
+
+fn default_watch_rescan_interval() -> u64 {
+    600
+}
+
+fn default_nav() -> Vec<NavEntry> {
+    let entry = |label: &str, url: &str, active: &[&str]| NavEntry {
+        label: String::from(label),
+        url: String::from(url),
+        active: active.iter().map(|a| String::from(*a)).collect(),
+    };
+    vec![
+        entry("nav_home", "/index/1.html", &["/index/"]),
+        entry("nav_photography", "/gallery/1.html", &["/gallery/"]),
+        entry(
+            "nav_category",
+            "/article_category.html",
+            &["/article_category.html", "/category/"],
+        ),
+        entry("nav_gallery", "/picture/1.html", &["/picture/"]),
+    ]
+}
+
+/// environment variables overriding config fields, e.g. ARCE_SCP_SERVER for scp_server
+pub const ENV_PREFIX: &str = "ARCE_";
+
+/// environment variable selecting the profile, when it's not given in the command line
+pub const PROFILE_ENV: &str = "ARCE_PROFILE";
+
+fn config_error(f: &Path, e: impl std::fmt::Display) -> err::Error {
+    err::Error::new(err::Reason::Config, format!("{:?}: {}", f, e))
+}
+
+fn read_yaml(f: &Path) -> Result<Value, err::Error> {
+    let yaml = std::fs::File::open(f).map_err(|e| config_error(f, e))?;
+    serde_yaml::from_reader(yaml).map_err(|e| config_error(f, e))
+}
+
+/// the overlay of a profile lies next to the base file, e.g. config.yaml to config.dev.yaml
+fn profile_file(f: &Path, profile: &str) -> PathBuf {
+    let stem = f.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match f.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, profile, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, profile),
+    };
+    f.with_file_name(name)
+}
+
+/// turning an env var into the value of a field, typed like the value it replaces
+/// it's parsed as yaml unless it replaces a string, and kept as a string if it's not valid yaml
+fn env_value(current: Option<&Value>, value: String) -> Value {
+    match current {
+        Some(Value::String(_)) => Value::String(value),
+        _ => serde_yaml::from_str(&value).unwrap_or(Value::String(value)),
+    }
+}
+
+/// overriding the fields with the ARCE_* env vars among vars
+fn apply_env(yaml: &mut Value, vars: impl IntoIterator<Item = (String, String)>) {
+    for (key, value) in vars {
+        let field = match key.strip_prefix(ENV_PREFIX) {
+            Some(field) if key != PROFILE_ENV => field.to_lowercase(),
+            _ => continue,
+        };
+        info!("Overriding config {} from {}", field, key);
+        if let Value::Mapping(ref mut m) = yaml {
+            let field = Value::String(field);
+            let value = env_value(m.get(&field), value);
+            m.insert(field, value);
+        }
+    }
+}
+
+/// merging the overlay into the base, mappings are merged by keys and others are replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(b) => merge(b, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// merging a profile overlay into the base config
+/// deploy is replaced as a whole, as the fields of one target don't apply to another
+fn merge_profile(base: &mut Value, mut overlay: Value) {
+    let deploy = Value::String(String::from("deploy"));
+    if let (Value::Mapping(base), Value::Mapping(overlay)) = (&mut *base, &mut overlay) {
+        if let Some(d) = overlay.remove(&deploy) {
+            base.insert(deploy, d);
+        }
+    }
+    merge(base, overlay);
 }
 
 /// OnceCell variable to make it static
-pub static CONFIG: OnceCell<GlobalConfig> = OnceCell::new();
+/// the config inside is swapped as a whole when reloaded, so readers keep a consistent one
+pub static CONFIG: OnceCell<RwLock<Arc<GlobalConfig>>> = OnceCell::new();
 
 impl GlobalConfig {
-    pub fn global() -> &'static GlobalConfig {
-        CONFIG.get().expect("Global Config is not initialized")
+    pub fn global() -> Arc<GlobalConfig> {
+        CONFIG
+            .get()
+            .expect("Global Config is not initialized")
+            .read()
+            .unwrap()
+            .clone()
     }
 
-    // reading the config file
-    pub fn from_file(f: PathBuf) -> Result<GlobalConfig, err::Error> {
-        let yaml = std::fs::File::open(f)?;
-        let config: GlobalConfig = serde_yaml::from_reader(yaml)?;
+    /// setting the global config, replacing the current one if any
+    pub fn set(config: GlobalConfig) {
+        let config = Arc::new(config);
+        if CONFIG.set(RwLock::new(config.clone())).is_err() {
+            *CONFIG.get().unwrap().write().unwrap() = config;
+        }
+    }
+
+    /// reading the layered config:
+    /// the base file, then the profile overlay such as config.prod.yaml, then ARCE_* env vars
+    pub fn load(f: PathBuf, profile: Option<&str>) -> Result<GlobalConfig, err::Error> {
+        let mut yaml = read_yaml(&f)?;
+        let mut sources = vec![f.clone()];
+
+        if let Some(profile) = profile {
+            let overlay = profile_file(&f, profile);
+            info!("Applying config profile {:?}", overlay);
+            merge_profile(&mut yaml, read_yaml(&overlay)?);
+            sources.push(overlay);
+        }
+
+        apply_env(&mut yaml, std::env::vars());
+
+        let mut config: GlobalConfig =
+            serde_yaml::from_value(yaml).map_err(|e| config_error(&f, e))?;
+
+        let base = f.parent().unwrap_or_else(|| Path::new(""));
+        config.db_path = base.join(&config.db_path);
+        config.output_dir = base.join(&config.output_dir);
+        config.template_dir = base.join(&config.template_dir);
+        config.static_dirs = config.static_dirs.iter().map(|d| base.join(d)).collect();
+        config.locale_dir = base.join(&config.locale_dir);
+        match config.deploy {
+            Some(DeployConfig::Local {
+                ref mut web_path,
+                ref mut pic_path,
+            }) => {
+                *web_path = base.join(&web_path);
+                *pic_path = base.join(&pic_path);
+            }
+            Some(DeployConfig::Git {
+                work_dir: Some(ref mut work_dir),
+                ..
+            }) => *work_dir = base.join(&work_dir),
+            _ => {}
+        }
+        config.translations = locale::load(&config.locale, &config.locale_dir)?;
+        config.sources = sources;
+
         info!("CONFIG READ {:?}", &config);
         Ok(config)
     }
+
+    /// checking the values which would otherwise fail later
+    /// returns every problem found, prefixed with its field name
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            problems.push(format!(
+                "url: {:?} should start with http:// or https://",
+                self.url
+            ));
+        }
+        if self.url.ends_with('/') {
+            problems.push(format!("url: {:?} should not end with /", self.url));
+        }
+
+        if self.pic_cloud_prefix.is_empty() {
+            problems.push(String::from("pic_cloud_prefix: should not be empty"));
+        }
+        if !self.pic_local.is_dir() {
+            problems.push(format!(
+                "pic_local: {:?} is not a directory",
+                self.pic_local
+            ));
+        }
+
+        match self.deploy_config() {
+            DeployConfig::Scp {
+                server, web_path, ..
+            } => {
+                let prefix = if self.deploy.is_some() {
+                    "deploy."
+                } else {
+                    "scp_"
+                };
+                if server.is_empty() {
+                    problems.push(format!("{}server: should not be empty", prefix));
+                }
+                if web_path.is_empty() {
+                    problems.push(format!("{}web_path: should not be empty", prefix));
+                }
+            }
+            DeployConfig::Rsync { web_path, .. } => {
+                if web_path.is_empty() {
+                    problems.push(String::from("deploy.web_path: should not be empty"));
+                }
+            }
+            DeployConfig::Local { web_path, pic_path } => {
+                if web_path.as_os_str().is_empty() {
+                    problems.push(String::from("deploy.web_path: should not be empty"));
+                }
+                if pic_path.as_os_str().is_empty() {
+                    problems.push(String::from("deploy.pic_path: should not be empty"));
+                }
+            }
+            DeployConfig::Git { remote, branch, .. } => {
+                if remote.is_empty() {
+                    problems.push(String::from("deploy.remote: should not be empty"));
+                }
+                if branch.is_empty() {
+                    problems.push(String::from("deploy.branch: should not be empty"));
+                }
+            }
+        }
+
+        if self.watch_poll_interval == 0 {
+            problems.push(String::from("watch_poll_interval: should be positive"));
+        }
+
+        if self.deploy_keep_releases == 0 {
+            problems.push(String::from("deploy_keep_releases: should be positive"));
+        }
+
+        if self.deploy_auto {
+            match self.deploy_interval {
+                None => problems.push(String::from(
+                    "deploy_interval: should be set when deploy_auto is true",
+                )),
+                Some(0) => problems.push(String::from("deploy_interval: should be positive")),
+                _ => {}
+            }
+        }
+
+        // AES-128 takes a 16 bytes iv
+        if let Some(ref iv) = self.iv {
+            if iv.len() != 16 {
+                problems.push(format!("iv: should be 16 bytes long, got {}", iv.len()));
+            }
+        }
+
+        if !self.template_dir.is_dir() {
+            problems.push(format!(
+                "template_dir: {:?} is not a directory",
+                self.template_dir
+            ));
+        }
+        for dir in &self.static_dirs {
+            if !dir.is_dir() {
+                problems.push(format!("static_dirs: {:?} is not a directory", dir));
+            }
+        }
+
+        for (i, entry) in self.nav.iter().enumerate() {
+            if entry.label.is_empty() {
+                problems.push(format!("nav: the label of entry {} should not be empty", i));
+            }
+            if entry.url.is_empty() {
+                problems.push(format!("nav: the url of entry {} should not be empty", i));
+            }
+        }
+
+        if self.translations.is_empty() {
+            problems.push(format!(
+                "locale: no translations found for {:?} in {:?}",
+                self.locale, self.locale_dir
+            ));
+        } else {
+            for key in locale::missing(&self.translations) {
+                problems.push(format!("locale: {:?} is not translated", key));
+            }
+        }
+
+        problems
+    }
+
+    /// the deploy target, made of the scp_* fields if deploy is not set
+    pub fn deploy_config(&self) -> DeployConfig {
+        match self.deploy {
+            Some(ref deploy) => deploy.clone(),
+            None => DeployConfig::Scp {
+                server: self.scp_server.clone(),
+                web_path: self.scp_web_path.clone(),
+                pic_path: self.scp_pic_path.clone(),
+            },
+        }
+    }
+
+    /// translating a ui string, the key itself is returned if it's not translated
+    pub fn t(&self, key: &str) -> String {
+        match self.translations.get(key) {
+            Some(s) => s.clone(),
+            None => String::from(key),
+        }
+    }
+
+    /// validating the config, see problems()
+    pub fn validate(&self) -> Result<(), err::Error> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(err::Error::new(err::Reason::Config, problems.join("; ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(s: &str) -> Value {
+        serde_yaml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn merges_mappings_by_keys() {
+        let mut base = yaml("{title: blog, nav: [a, b], watch: {mode: native, interval: 5}}");
+        merge(
+            &mut base,
+            yaml("{nav: [c], watch: {interval: 10}, robot: robots.txt}"),
+        );
+        assert_eq!(
+            base,
+            yaml("{title: blog, nav: [c], watch: {mode: native, interval: 10}, robot: robots.txt}")
+        );
+    }
+
+    #[test]
+    fn replaces_the_deploy_target_as_a_whole() {
+        let mut base = yaml(
+            "{title: blog, deploy: {method: rsync, server: prodcloud, web_path: /web, pic_path: /pic}}",
+        );
+        merge_profile(
+            &mut base,
+            yaml("{deploy: {method: rsync, web_path: /tmp/web, pic_path: /tmp/pic}}"),
+        );
+        assert_eq!(
+            base,
+            yaml("{title: blog, deploy: {method: rsync, web_path: /tmp/web, pic_path: /tmp/pic}}")
+        );
+        let deploy: DeployConfig = serde_yaml::from_value(base["deploy"].clone()).unwrap();
+        assert_eq!(deploy.target(), "rsync:/tmp/web");
+
+        // the base target is kept if the overlay doesn't set one
+        merge_profile(&mut base, yaml("{title: dev}"));
+        assert_eq!(base["deploy"]["web_path"], yaml("/tmp/web"));
+    }
+
+    #[test]
+    fn finds_the_profile_next_to_the_base_file() {
+        assert_eq!(
+            profile_file(Path::new("/blog/config.yaml"), "dev"),
+            PathBuf::from("/blog/config.dev.yaml")
+        );
+        assert_eq!(
+            profile_file(Path::new("blog/config"), "prod"),
+            PathBuf::from("blog/config.prod")
+        );
+    }
+
+    #[test]
+    fn parses_env_vars_like_the_values_they_replace() {
+        let mut config = yaml("{title: '2024', deploy_auto: false, pic_compress_threshold: 100}");
+        apply_env(
+            &mut config,
+            [
+                ("ARCE_TITLE", "2025"),
+                ("ARCE_DEPLOY_AUTO", "true"),
+                ("ARCE_DEPLOY_INTERVAL", "60"),
+                ("ARCE_STATIC_DIRS", "[css, js]"),
+                (
+                    "ARCE_DEPLOY",
+                    "{method: local, web_path: web, pic_path: pic}",
+                ),
+                ("ARCE_SCP_SERVER", "prod: cloud: 1"),
+                ("ARCE_PROFILE", "dev"),
+                ("HOME", "/root"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        assert_eq!(
+            config,
+            yaml(
+                "{title: '2025', deploy_auto: true, pic_compress_threshold: 100, \
+                deploy_interval: 60, static_dirs: [css, js], \
+                deploy: {method: local, web_path: web, pic_path: pic}, \
+                scp_server: 'prod: cloud: 1'}"
+            )
+        );
+    }
 }
@@ -0,0 +1,45 @@
+use std::fmt::{Display, Formatter};
+
+/// the files of the output folder a deploy transfers, relative to the output folder
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// the added and changed files
+    pub copied: Vec<String>,
+    /// the files no longer generated, empty if deploy_delete is off
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.copied.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// what a deploy did, compared with the last one
+#[derive(Debug, Default, Clone)]
+pub struct DeploySummary {
+    /// the folder of the release on the target, named after the time
+    /// none for the deploys before releases
+    pub release: Option<String>,
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub method: String,
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// whether the site points to the release
+    pub live: bool,
+    /// whether the release is removed from the target
+    pub pruned: bool,
+}
+
+impl Display for DeploySummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} removed, {} unchanged",
+            self.added, self.changed, self.removed, self.unchanged
+        )
+    }
+}
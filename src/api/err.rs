@@ -8,8 +8,8 @@ pub enum Reason {
     ArticleRender,
     Filesystem,
     PictureProcess,
-    // Config,       Config errors are unwrapped directly
-    // Internet,     Internet errors are addressed directly
+    Config,
+    Deploy,
     Internal,
 }
 
@@ -18,8 +18,8 @@ impl Display for Reason {
         let s = match self {
             Reason::Database => "Sqlite or R2d2 Error",
             Reason::Filesystem => "File Notification Error",
-            // Reason::Config => "config.yaml Error",
-            // Reason::Internet => "SSH Error",
+            Reason::Config => "Config Error",
+            Reason::Deploy => "Deploy Error",
             Reason::Internal => "Tera or other Crates Error",
             Reason::PictureProcess => "Error Processing the Image",
             Reason::ArticleRender => "Error Rendering Markdown to HTML",
@@ -118,3 +118,20 @@ impl From<sitemap::Error> for Error {
         }
     }
 }
+
+impl From<tera::Error> for Error {
+    fn from(err: tera::Error) -> Self {
+        warn!("{:?}", err);
+        // the message of a tera error names the template only, the cause is in its sources
+        let mut message = err.to_string();
+        let mut source = std::error::Error::source(&err);
+        while let Some(e) = source {
+            message = message + ", " + &e.to_string();
+            source = e.source();
+        }
+        Error {
+            reason: Reason::Internal,
+            message,
+        }
+    }
+}
@@ -1,17 +1,130 @@
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::api::err;
 
 pub type ArticleFolderList = Vec<ArticleFolder>;
 pub type PictureFolderList = Vec<PictureFolder>;
 
-#[derive(Debug)]
+/// how a folder is watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    /// the events of the os, such as inotify
+    Native,
+    /// checking the files every watch_poll_interval seconds, for network and synced folders
+    Poll,
+    /// both of them
+    Hybrid,
+}
+
+impl WatchMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatchMode::Native => "native",
+            WatchMode::Poll => "poll",
+            WatchMode::Hybrid => "hybrid",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, err::Error> {
+        match s {
+            "native" => Ok(WatchMode::Native),
+            "poll" => Ok(WatchMode::Poll),
+            "hybrid" => Ok(WatchMode::Hybrid),
+            _ => Err(err::Error::new(
+                err::Reason::Database,
+                format!("Unknown watch mode {}", s),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ArticleFolder {
     pub path: PathBuf,
     pub deploy: String,
     pub need_confirm: bool,
+    /// watch_mode in the config is used if not set
+    pub watch: Option<WatchMode>,
 }
 
-#[derive(Debug)]
-
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PictureFolder {
     pub path: PathBuf,
+    /// watch_mode in the config is used if not set
+    pub watch: Option<WatchMode>,
+}
+
+/// the state of the watcher of a folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatcherState {
+    Running,
+    /// waiting to be restarted after a failure
+    Restarting,
+    /// waiting for the folder to be reachable, such as an unmounted drive
+    Unreachable,
+    Stopped,
+}
+
+impl WatcherState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatcherState::Running => "running",
+            WatcherState::Restarting => "restarting",
+            WatcherState::Unreachable => "unreachable",
+            WatcherState::Stopped => "stopped",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, err::Error> {
+        match s {
+            "running" => Ok(WatcherState::Running),
+            "restarting" => Ok(WatcherState::Restarting),
+            "unreachable" => Ok(WatcherState::Unreachable),
+            "stopped" => Ok(WatcherState::Stopped),
+            _ => Err(err::Error::new(
+                err::Reason::Database,
+                format!("Unknown watcher state {}", s),
+            )),
+        }
+    }
+}
+
+/// a watcher not seen for this many seconds is no longer running, e.g. arce was killed
+/// the running ones are seen every few seconds
+pub const SEEN_TIMEOUT: u64 = 30;
+
+/// how the watcher of a folder is doing, times are unix timestamps
+#[derive(Debug, Clone)]
+pub struct FolderHealth {
+    pub path: PathBuf,
+    pub state: WatcherState,
+    /// restarts since the watcher was started
+    pub restarts: u32,
+    /// the last time a change is handled
+    pub last_event: Option<u64>,
+    pub last_error: Option<String>,
+    pub error_time: Option<u64>,
+    /// the last time the watcher is known to be alive
+    pub seen: u64,
+}
+
+impl FolderHealth {
+    /// the state, stopped if the watcher is no longer seen
+    pub fn current_state(&self) -> WatcherState {
+        if now() > self.seen + SEEN_TIMEOUT {
+            return WatcherState::Stopped;
+        }
+        self.state
+    }
+}
+
+/// the current unix timestamp
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
@@ -0,0 +1,59 @@
+use crate::api::err;
+
+use log::info;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// the ui strings of a locale, keyed by the names used in the templates
+pub type Translations = HashMap<String, String>;
+
+/// the locales shipped with the binary, used when there's no file for them
+const BUILTIN: [(&str, &str); 2] = [
+    ("zh", include_str!("../../locale/zh.yaml")),
+    ("en", include_str!("../../locale/en.yaml")),
+];
+
+/// every locale should translate the keys of the default one
+pub const DEFAULT_LOCALE: &str = "zh";
+
+fn parse(s: &str, source: &str) -> Result<Translations, err::Error> {
+    serde_yaml::from_str(s).map_err(|e| {
+        err::Error::new(
+            err::Reason::Config,
+            format!("translations {}: {}", source, e),
+        )
+    })
+}
+
+fn builtin(locale: &str) -> Translations {
+    BUILTIN
+        .iter()
+        .find(|(name, _)| *name == locale)
+        .map(|(name, s)| parse(s, name).expect("Invalid builtin translations"))
+        .unwrap_or_default()
+}
+
+/// loading the translations of a locale
+/// <dir>/<locale>.yaml is read if it exists, overriding the builtin strings key by key
+pub fn load(locale: &str, dir: &Path) -> Result<Translations, err::Error> {
+    let mut translations = builtin(locale);
+
+    let f = dir.join(format!("{}.yaml", locale));
+    if f.is_file() {
+        info!("Reading translations {:?}", f);
+        let s = std::fs::read_to_string(&f)?;
+        translations.extend(parse(&s, &format!("{:?}", f))?);
+    }
+
+    Ok(translations)
+}
+
+/// the keys of the default locale that are not translated
+pub fn missing(translations: &Translations) -> Vec<String> {
+    let mut keys: Vec<String> = builtin(DEFAULT_LOCALE)
+        .into_keys()
+        .filter(|k| !translations.contains_key(k))
+        .collect();
+    keys.sort();
+    keys
+}
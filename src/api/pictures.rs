@@ -1,14 +1,15 @@
 use exif::{In, Tag};
 use imagesize::size;
 use log::{info, warn};
+use rusqlite::{Transaction, TransactionBehavior};
 use serde::Serialize;
 use std::path::PathBuf;
-use std::process::Command;
 
 use crate::api::config::GlobalConfig;
 use crate::api::err;
 use crate::api::sync::GlobalConnPool;
 use crate::model::pictures::{find_picture, insert_photography_picture, insert_picture};
+use crate::model::uploads::queue_upload;
 
 pub type PPictureList = Vec<PhotographyPicture>;
 
@@ -176,26 +177,16 @@ impl PhotographyPicture {
         Ok(self)
     }
 
-    /// Register in database and update to server
-    pub fn register_and_upload(&mut self) -> Result<(), err::Error> {
-        // Register
-        let config = GlobalConfig::global();
+    /// Register in database and queue it for uploading to server
+    pub fn register_and_queue(&mut self) -> Result<(), err::Error> {
         let conn = GlobalConnPool::global().0.get()?;
-        insert_photography_picture(&conn, self)?;
-
-        // Upload
-        let dst = config.scp_server.clone()
-            + ":"
-            + &config.scp_pic_path
-            + "/"
-            + self.path.file_name().unwrap().to_str().unwrap();
-
-        match Command::new("scp").arg(&self.path).arg(&dst).output() {
-            Err(_) => {
-                warn!("Upload of picture {:?} to {:?} failed.", &self.path, &dst);
-            }
-            _ => {}
-        };
+        // queued for the target set in the config, others get it queued when uploaded to
+        let target = GlobalConfig::global().deploy_config().target();
+        // it's looked up before inserted, so the write lock is taken first
+        let tx = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+        let path = insert_photography_picture(&tx, self)?;
+        queue_upload(&tx, &target, &path)?;
+        tx.commit()?;
         Ok(())
     }
 }
@@ -219,7 +210,7 @@ impl Picture {
         })
     }
 
-    /// storing in filesystem, registering in db, and uploading to server
+    /// storing in filesystem, registering in db, and queueing it for uploading to server
     pub fn register(mut self) -> Result<PathBuf, err::Error> {
         let config = GlobalConfig::global();
         let conn = GlobalConnPool::global().0.get().unwrap();
@@ -234,24 +225,14 @@ impl Picture {
         std::fs::copy(&self.path, &to)?;
         self.path = to;
 
-        // Registering
-        // MAYBE a quick return should happen here?
-        let path = insert_picture(&conn, &self)?;
+        // Registering and queueing
+        let target = config.deploy_config().target();
+        let tx = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+        let path = insert_picture(&tx, &self)?;
+        queue_upload(&tx, &target, &path)?;
+        tx.commit()?;
         self.path = path;
 
-        // Uploading
-        let dst = config.scp_server.clone()
-            + ":"
-            + &config.scp_pic_path
-            + "/"
-            + self.path.clone().file_name().unwrap().to_str().unwrap();
-        match Command::new("scp").arg(&self.path).arg(&dst).output() {
-            Err(_) => {
-                warn!("Upload of picture {:?} to {:?} failed.", &self.path, &dst);
-            }
-            _ => {}
-        };
-
         Ok(self.path)
     }
 }
@@ -287,8 +268,8 @@ impl From<PhotographyPicture> for PhotographyPictureBrief {
         PhotographyPictureBrief {
             selected: p.selected,
             title: p.title,
-            linked: !(p.article_link == None),
-            article_link: p.article_link.unwrap_or(String::new()),
+            linked: p.article_link.is_some(),
+            article_link: p.article_link.unwrap_or_default(),
             url: p.path.file_name().unwrap().to_str().unwrap().to_string(),
             params: p.params,
             date: p.date,
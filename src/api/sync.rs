@@ -1,5 +1,6 @@
 use once_cell::sync::OnceCell;
-use std::sync::Mutex;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
 
 /// Global Database Connection Pool
 pub type ConnPool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
@@ -39,3 +40,39 @@ impl NeedPublish {
         NEED_PUBLISH.get().expect("Error Getting Need Publish Var")
     }
 }
+
+/// A global counter of finished renders, waited on by the preview server
+#[derive(Debug, Default)]
+pub struct Rendered {
+    count: Mutex<u64>,
+    cond: Condvar,
+}
+
+pub static RENDERED: OnceCell<Rendered> = OnceCell::new();
+
+impl Rendered {
+    /// called every time the site is rendered
+    pub fn bump(&self) {
+        *self.count.lock().unwrap() += 1;
+        self.cond.notify_all();
+    }
+
+    pub fn get(&self) -> u64 {
+        *self.count.lock().unwrap()
+    }
+
+    /// block until the counter differs from seen, or timeout
+    /// returns the current counter
+    pub fn wait(&self, seen: u64, timeout: Duration) -> u64 {
+        let count = self.count.lock().unwrap();
+        let (count, _) = self
+            .cond
+            .wait_timeout_while(count, timeout, |c| *c == seen)
+            .unwrap();
+        *count
+    }
+
+    pub fn global() -> &'static Rendered {
+        RENDERED.get_or_init(Rendered::default)
+    }
+}
@@ -1,55 +1,487 @@
 use api::{
-    config::{GlobalConfig, CONFIG},
-    folders::{ArticleFolder, PictureFolder},
+    config::{GlobalConfig, PROFILE_ENV},
+    err,
+    folders::{now, ArticleFolder, PictureFolder, WatchMode},
     sync::{GlobalConnPool, NeedPublish, CONN_POOL, NEED_PUBLISH},
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use env_logger::Env;
-use log::info;
-use model::folders::{add_article_folder, add_picture_folder};
+use log::{info, warn};
+use model::folders::{
+    add_article_folder, add_picture_folder, get_article_folder, get_article_folders,
+    get_picture_folder, get_picture_folders, remove_article_folder, remove_picture_folder,
+    update_article_folder, update_picture_folder,
+};
+use model::health::get_folder_health;
 use r2d2_sqlite::SqliteConnectionManager;
-use std::path::PathBuf;
-use text_io::read;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::thread;
 
 mod api;
 mod model;
 mod notifier;
 mod publisher;
+mod server;
 
-#[derive(Debug, Parser)]
+/// A minimal blog generator for photographers
+#[derive(Debug, Clone, Parser)]
+#[clap(name = "arce", version)]
 struct Args {
-    #[clap(short = 'c', long = "config", value_parser)]
+    /// Path of the config file
+    #[clap(short = 'c', long = "config", value_parser, global = true)]
     config_file: Option<String>,
+
+    /// Profile overlaid on the config, e.g. `prod` reads config.prod.yaml [env: ARCE_PROFILE]
+    #[clap(short = 'p', long = "profile", value_parser, global = true)]
+    profile: Option<String>,
+
+    /// Path of the database file, overriding db_path in the config
+    #[clap(long = "db", value_parser, global = true)]
+    db_path: Option<PathBuf>,
+
+    /// Folder the site is rendered into, overriding output_dir in the config
+    #[clap(short = 'o', long = "output", value_parser, global = true)]
+    output_dir: Option<PathBuf>,
+
+    /// Folder of the templates, overriding template_dir in the config
+    #[clap(long = "template-dir", value_parser, global = true)]
+    template_dir: Option<PathBuf>,
+
+    /// Folder copied into the output as it is, overriding static_dirs in the config
+    #[clap(long = "static-dir", value_parser, global = true)]
+    static_dirs: Vec<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Command,
 }
 
-/// Initialize Main
-/// Parse The Args, Read the Config, and init many others
-/// including threads and global vars
-fn init() {
-    info!("Initializing");
-    let args: Args = Args::parse();
-    let f = PathBuf::from(args.config_file.unwrap_or(String::from("config.yaml")));
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Manage the monitored article and picture folders
+    #[clap(subcommand)]
+    Folder(FolderCommand),
+    /// Scan all the folders, render the site into public/ once and exit
+    Build {
+        /// Deploy the site after rendering
+        #[clap(long, action)]
+        deploy: bool,
+    },
+    /// Deploy the rendered site in public/ to the server
+    Deploy,
+    /// Switch the site back to an earlier release, the one before the live one by default
+    Rollback {
+        /// Name of the release, as listed by `releases`
+        #[clap(value_parser)]
+        release: Option<String>,
+    },
+    /// List the deployed releases, the oldest first
+    Releases,
+    /// Watch the folders and publish the site periodically
+    Daemon,
+    /// Check the config file, reporting every problem found
+    CheckConfig,
+    /// Watch the folders and preview the site locally, reloading pages after each render
+    Serve {
+        /// Address to listen on
+        #[clap(short, long, value_parser, default_value = "127.0.0.1:8000")]
+        bind: String,
+    },
+}
 
-    let manager = SqliteConnectionManager::file("arce.db");
-    let global_conn_pool = GlobalConnPool(r2d2::Pool::new(manager).unwrap());
+#[derive(Debug, Clone, Subcommand)]
+enum FolderCommand {
+    /// Monitor a folder of markdown articles
+    AddArticle {
+        /// Local path of the folder
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// Folder on the site the articles are deployed to, e.g. `article`
+        #[clap(short, long, value_parser)]
+        deploy: String,
+        /// Only publish articles containing `deploy: true`
+        #[clap(long, action)]
+        confirm: bool,
+        /// How the folder is watched, watch_mode in the config by default
+        #[clap(long, value_enum)]
+        watch: Option<WatchMode>,
+    },
+    /// Monitor a folder of pictures
+    AddPicture {
+        /// Local path of the folder
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// How the folder is watched, watch_mode in the config by default
+        #[clap(long, value_enum)]
+        watch: Option<WatchMode>,
+    },
+    /// Change the settings of a folder, only --watch applies to picture folders
+    Update {
+        /// Local path of the folder
+        #[clap(value_parser)]
+        path: PathBuf,
+        /// New folder on the site the articles are deployed to
+        #[clap(short, long, value_parser)]
+        deploy: Option<String>,
+        /// Whether only articles containing `deploy: true` are published
+        #[clap(long, value_parser)]
+        confirm: Option<bool>,
+        /// How the folder is watched
+        #[clap(long, value_enum)]
+        watch: Option<WatchMode>,
+    },
+    /// List the monitored folders
+    List,
+    /// Show how the watcher of each folder is doing in the running daemon or serve
+    Status,
+    /// Stop monitoring a folder, unpublishing its articles
+    Remove {
+        /// Local path of the folder
+        #[clap(value_parser)]
+        path: PathBuf,
+    },
+}
+
+/// Initialize the database connection pool and tables
+fn init_db() -> Result<(), err::Error> {
+    let manager = SqliteConnectionManager::file(&GlobalConfig::global().db_path)
+        .with_init(model::init_connection);
+    let global_conn_pool = GlobalConnPool(r2d2::Pool::new(manager)?);
     CONN_POOL.set(global_conn_pool).unwrap();
 
-    let need_publish = NeedPublish::new(false);
-    NEED_PUBLISH.set(need_publish).unwrap();
+    crate::model::init()
+}
 
-    let config = GlobalConfig::from_file(f).expect("Reading Config file failed");
-    CONFIG.set(config).unwrap();
+/// Read the layered config, and apply the overrides in the command line
+fn read_config(args: &Args) -> Result<GlobalConfig, err::Error> {
+    let f = PathBuf::from(
+        args.config_file
+            .clone()
+            .unwrap_or_else(|| String::from("config.yaml")),
+    );
+    let profile = args
+        .profile
+        .clone()
+        .or_else(|| std::env::var(PROFILE_ENV).ok());
+    let mut config = GlobalConfig::load(f, profile.as_deref())?;
 
-    crate::model::init().expect("Initialize DB failed");
+    if let Some(ref db_path) = args.db_path {
+        config.db_path = db_path.clone();
+    }
+    if let Some(ref output_dir) = args.output_dir {
+        config.output_dir = output_dir.clone();
+    }
+    if let Some(ref template_dir) = args.template_dir {
+        config.template_dir = template_dir.clone();
+    }
+    if !args.static_dirs.is_empty() {
+        config.static_dirs = args.static_dirs.clone();
+    }
 
-    crate::notifier::init().expect("Initialize Article Notifier failed");
+    // pictures are served from pic_local instead of the cloud
+    // and the site is rendered next to the output folder, leaving the built one untouched
+    if let Command::Serve { .. } = args.command {
+        config.pic_cloud_prefix = String::from(server::PIC_PREFIX);
+        config.output_dir =
+            crate::publisher::sibling(&config.output_dir, crate::publisher::PREVIEW_SUFFIX);
+        config.preview = true;
+    }
 
-    crate::publisher::start();
+    Ok(config)
+}
+
+/// Initialize the watchers of all the folders
+/// including threads and global vars
+fn init_watchers() -> Result<(), err::Error> {
+    info!("Initializing");
+
+    let need_publish = NeedPublish::new(false);
+    NEED_PUBLISH.set(need_publish).unwrap();
+
+    // the articles are kept from the last run, and reconciled as each folder is scanned
+    crate::notifier::init()?;
 
     NeedPublish::global().set(true);
 
     info!("Initialized");
+    Ok(())
+}
+
+/// Resolve a folder given in the command line into an absolute path
+fn folder_path(path: PathBuf) -> Result<PathBuf, err::Error> {
+    if !path.is_dir() {
+        return Err(err::Error::new(
+            err::Reason::Filesystem,
+            format!("{:?} is not a directory", path),
+        ));
+    }
+    Ok(path.canonicalize()?)
+}
+
+/// Normalize the deploy folder given in the command line
+fn deploy_folder(deploy: &str) -> Result<String, err::Error> {
+    let deploy = deploy.trim_matches('/').to_string();
+    if deploy.is_empty() {
+        return Err(err::Error::new(
+            err::Reason::Internal,
+            String::from("deploy folder can't be empty"),
+        ));
+    }
+    Ok(deploy)
+}
+
+fn folder(cmd: FolderCommand) -> Result<(), err::Error> {
+    let conn = GlobalConnPool::global().0.get()?;
+    match cmd {
+        FolderCommand::AddArticle {
+            path,
+            deploy,
+            confirm,
+            watch,
+        } => {
+            let f = ArticleFolder {
+                path: folder_path(path)?,
+                deploy: deploy_folder(&deploy)?,
+                need_confirm: confirm,
+                watch,
+            };
+            println!("Adding article folder {:?}", f.path);
+            add_article_folder(&conn, f)?;
+        }
+        FolderCommand::AddPicture { path, watch } => {
+            let f = PictureFolder {
+                path: folder_path(path)?,
+                watch,
+            };
+            println!("Adding picture folder {:?}", f.path);
+            add_picture_folder(&conn, f)?;
+        }
+        FolderCommand::Update {
+            path,
+            deploy,
+            confirm,
+            watch,
+        } => {
+            let path = path.canonicalize().unwrap_or(path);
+            if let Some(mut f) = get_article_folder(&conn, &path)? {
+                if let Some(deploy) = deploy {
+                    f.deploy = deploy_folder(&deploy)?;
+                }
+                if let Some(confirm) = confirm {
+                    f.need_confirm = confirm;
+                }
+                if watch.is_some() {
+                    f.watch = watch;
+                }
+                update_article_folder(&conn, f)?;
+                println!("Updated article folder {:?}", path);
+                return Ok(());
+            }
+
+            let mut f = get_picture_folder(&conn, &path)?.ok_or_else(|| {
+                err::Error::new(
+                    err::Reason::Database,
+                    format!("{:?} is not a monitored folder", path),
+                )
+            })?;
+            if deploy.is_some() || confirm.is_some() {
+                return Err(err::Error::new(
+                    err::Reason::Config,
+                    String::from("only --watch applies to picture folders"),
+                ));
+            }
+            if watch.is_some() {
+                f.watch = watch;
+            }
+            update_picture_folder(&conn, f)?;
+            println!("Updated picture folder {:?}", path);
+        }
+        FolderCommand::List => {
+            let mode = |w: Option<WatchMode>| w.map(|w| w.as_str()).unwrap_or("default");
+            for f in get_article_folders(&conn)? {
+                println!(
+                    "article\t{}\tdeploy: {}\tconfirm: {}\twatch: {}",
+                    f.path.display(),
+                    f.deploy,
+                    f.need_confirm,
+                    mode(f.watch)
+                );
+            }
+            for f in get_picture_folders(&conn)? {
+                println!("picture\t{}\twatch: {}", f.path.display(), mode(f.watch));
+            }
+        }
+        FolderCommand::Status => {
+            for f in get_article_folders(&conn)? {
+                folder_status(&conn, "article", &f.path)?;
+            }
+            for f in get_picture_folders(&conn)? {
+                folder_status(&conn, "picture", &f.path)?;
+            }
+        }
+        FolderCommand::Remove { path } => {
+            // the folder may have been deleted already, so the path is not resolved
+            let path = path.canonicalize().unwrap_or(path);
+            let removed =
+                remove_article_folder(&conn, &path)? + remove_picture_folder(&conn, &path)?;
+            if removed == 0 {
+                return Err(err::Error::new(
+                    err::Reason::Database,
+                    format!("{:?} is not a monitored folder", path),
+                ));
+            }
+            println!("Removed folder {:?}", path);
+        }
+    }
+    Ok(())
+}
+
+/// Print how long ago a unix timestamp is
+fn ago(time: u64) -> String {
+    let secs = now().saturating_sub(time);
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Print the health of the watcher of a folder
+fn folder_status(conn: &Connection, kind: &str, path: &Path) -> Result<(), err::Error> {
+    let h = match get_folder_health(conn, path)? {
+        Some(h) => h,
+        None => {
+            println!("{}\t{}\tnever watched", kind, path.display());
+            return Ok(());
+        }
+    };
+    let last_event = h
+        .last_event
+        .map(ago)
+        .unwrap_or_else(|| String::from("none"));
+    let last_error = match (&h.last_error, h.error_time) {
+        (Some(e), Some(time)) => format!("{}, {}", ago(time), e),
+        _ => String::from("none"),
+    };
+    println!(
+        "{}\t{}\t{}\trestarts: {}\tlast event: {}\tlast error: {}",
+        kind,
+        h.path.display(),
+        h.current_state().as_str(),
+        h.restarts,
+        last_event,
+        last_error
+    );
+    Ok(())
+}
+
+/// Build the site once without starting any watcher
+fn build(deploy: bool) -> Result<(), err::Error> {
+    info!("Building");
+
+    crate::notifier::scan()?;
+
+    crate::publisher::init()?;
+    crate::publisher::build(true)?;
+    if deploy {
+        crate::publisher::deploy()?;
+    }
+
+    info!("Built");
+    Ok(())
+}
+
+/// Print the deployed releases
+fn releases() -> Result<(), err::Error> {
+    for r in crate::publisher::deployer::releases()? {
+        let state = if r.live {
+            "live"
+        } else if r.pruned {
+            "removed"
+        } else {
+            ""
+        };
+        println!(
+            "{}\t{}\t{}\t{}",
+            r.release.clone().unwrap_or_default(),
+            r.method,
+            r,
+            state
+        );
+    }
+    Ok(())
+}
+
+/// Validate the config and make it global, then initialize the database
+fn init(config: GlobalConfig) -> Result<(), err::Error> {
+    config.validate()?;
+    GlobalConfig::set(config);
+    init_db()
+}
+
+/// Print the problems of the config
+fn check_config(config: &GlobalConfig) -> Result<(), err::Error> {
+    let problems = config.problems();
+    if problems.is_empty() {
+        println!("Config OK");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    Err(err::Error::new(
+        err::Reason::Config,
+        format!("{} problem(s) found", problems.len()),
+    ))
+}
+
+fn run(args: Args) -> Result<(), err::Error> {
+    let config = read_config(&args)?;
+    match args.command.clone() {
+        // the only command running with an invalid config
+        Command::CheckConfig => check_config(&config),
+        Command::Folder(cmd) => {
+            init(config)?;
+            folder(cmd)
+        }
+        Command::Build { deploy } => {
+            init(config)?;
+            build(deploy)
+        }
+        Command::Deploy => {
+            init(config)?;
+            crate::publisher::deploy()
+        }
+        Command::Rollback { release } => {
+            init(config)?;
+            let release = crate::publisher::rollback(release.as_deref())?;
+            println!("Rolled back to release {}", release);
+            Ok(())
+        }
+        Command::Releases => {
+            init(config)?;
+            releases()
+        }
+        Command::Daemon => {
+            init(config)?;
+            init_watchers()?;
+            notifier::config_file::watch_config(move || read_config(&args));
+            crate::publisher::start();
+            // all the work is done by the notifier and publisher threads
+            loop {
+                thread::park();
+            }
+        }
+        Command::Serve { bind } => {
+            init(config)?;
+            init_watchers()?;
+            notifier::config_file::watch_config(move || read_config(&args));
+            crate::publisher::start_preview();
+            server::serve(&bind)
+        }
+    }
 }
 
 fn main() {
@@ -60,36 +492,10 @@ fn main() {
     env_logger::init_from_env(env);
     info!("Logger Initialized");
 
-    init();
-
-    // loop for adding folders
-    // it's only a temporary solution
-    loop {
-        let t: i32 = read!();
-        match t {
-            // 1 means Article folder
-            1 => {
-                let conn = GlobalConnPool::global().0.get().unwrap();
-                let path: String = read!();
-                let path = PathBuf::from(path);
-                let deploy: String = read!();
-                let need_confirm: bool = read!();
-                let f = ArticleFolder {
-                    path,
-                    deploy,
-                    need_confirm,
-                };
-                add_article_folder(&conn, f).unwrap();
-            }
-            // 2 means Picture folder
-            2 => {
-                let conn = GlobalConnPool::global().0.get().unwrap();
-                let path: String = read!();
-                let path = PathBuf::from(path);
-                let f = PictureFolder { path };
-                add_picture_folder(&conn, f).unwrap();
-            }
-            _ => {}
-        }
+    let args: Args = Args::parse();
+    if let Err(e) = run(args) {
+        warn!("{}", e);
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
 }
@@ -3,16 +3,33 @@
  */
 use crate::api::{err, sync::GlobalConnPool};
 
+use rusqlite::Connection;
+use std::time::Duration;
+
+pub mod article_cache;
 pub mod articles;
+pub mod deploys;
 pub mod folders;
+pub mod health;
+pub mod migrations;
+pub mod outputs;
 pub mod pictures;
+pub mod uploads;
+
+/// how long a connection waits for another one writing, before giving up
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// setting up each connection of the pool
+/// the watchers and the publisher write at the same time, so WAL is used
+pub fn init_connection(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    Ok(())
+}
 
 /// init the database and tables
 /// currently using the global conn pool
 pub fn init() -> Result<(), err::Error> {
-    let conn = GlobalConnPool::global().0.get().unwrap();
-    folders::init(&conn)?;
-    articles::init(&conn)?;
-    pictures::init(&conn)?;
-    Ok(())
+    let mut conn = GlobalConnPool::global().0.get()?;
+    migrations::migrate(&mut conn)
 }
@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::api::articles::{Article, CachedArticle};
+use crate::api::err;
+
+/// getting the cached article of a source file
+/// the preview server keeps its own, as its pictures link to the local ones
+pub fn get_cached_article(
+    conn: &Connection,
+    p: &Path,
+    preview: bool,
+) -> Result<Option<CachedArticle>, err::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM article_cache WHERE PREVIEW = ?1 AND PATH = ?2")?;
+    let row = stmt
+        .query_row(params![preview, p.to_str()], |row| {
+            Ok((
+                row.get::<&str, String>("PATH")?,
+                row.get::<&str, String>("HASH")?,
+                row.get::<&str, String>("FRONT_MATTER")?,
+                row.get::<&str, String>("CONTENT")?,
+                row.get::<&str, String>("PICTURES")?,
+            ))
+        })
+        .optional()?;
+
+    let (path, hash, front_matter, content, pictures) = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let mut article: Article = serde_yaml::from_str(&front_matter)?;
+    article.content = content;
+    Ok(Some(CachedArticle {
+        path: PathBuf::from(path),
+        hash,
+        article,
+        pictures: serde_yaml::from_str(&pictures)?,
+    }))
+}
+
+/// caching a processed article, replacing the old one
+/// the password isn't cached, it's read from the source again
+pub fn update_cached_article(
+    conn: &Connection,
+    c: &CachedArticle,
+    preview: bool,
+) -> Result<(), err::Error> {
+    // the rendered html is kept in its own column
+    let front_matter = Article {
+        content: String::new(),
+        password: String::new(),
+        ..c.article.clone()
+    };
+
+    let mut stmt = conn.prepare(
+        "INSERT or REPLACE INTO article_cache\
+        (PREVIEW, PATH, HASH, FRONT_MATTER, CONTENT, PICTURES)\
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    stmt.execute(params![
+        preview,
+        c.path.to_str(),
+        c.hash,
+        serde_yaml::to_string(&front_matter)?,
+        c.article.content,
+        serde_yaml::to_string(&c.pictures)?,
+    ])?;
+    Ok(())
+}
+
+/// deleting the cache of the articles no longer published
+pub fn prune_article_cache(conn: &Connection) -> Result<usize, err::Error> {
+    Ok(conn.execute(
+        "DELETE FROM article_cache WHERE PATH NOT IN (SELECT PATH FROM articles)",
+        [],
+    )?)
+}
@@ -1,6 +1,5 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use log::info;
 use rusqlite::params;
 use rusqlite::Connection;
 
@@ -56,21 +55,18 @@ pub fn delete_article(conn: &Connection, p: PathBuf) -> Result<(), err::Error> {
     Ok(())
 }
 
-/// initializing the articles table
-pub fn init(conn: &Connection) -> Result<(), err::Error> {
-    info!("Initializing Articles Model");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS articles (\
-        PATH            TEXT        NOT NULL,\
-        DEPLOY_FOLDER   BOOLEAN     NOT NULL,\
-        TIME            INTEGER     NOT NULL,\
-        PRIMARY KEY(PATH, DEPLOY_FOLDER)\
-        )",
-        [],
-    )?;
-
-    // in current version, initializing means scanning all the monitored folders
-    conn.execute("DELETE FROM articles", [])?;
+/// getting the articles inside a folder
+pub fn get_articles_in(conn: &Connection, folder: &Path) -> Result<ArticleList, err::Error> {
+    let mut articles = get_articles(conn)?;
+    // article folders are monitored non-recursively
+    articles.retain(|a| a.path.parent() == Some(folder));
+    Ok(articles)
+}
 
+/// deleting all the articles inside a folder
+pub fn delete_articles_in(conn: &Connection, folder: &Path) -> Result<(), err::Error> {
+    for a in get_articles_in(conn, folder)? {
+        delete_article(conn, a.path)?;
+    }
     Ok(())
 }
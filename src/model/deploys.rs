@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::api::deploys::DeploySummary;
+use crate::api::err;
+
+/// getting the files on a deploy target, with the hashes of their contents
+/// paths are relative to the root of the site
+pub fn get_deployed_files(
+    conn: &Connection,
+    target: &str,
+) -> Result<HashMap<String, String>, err::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM deployed WHERE TARGET = ?1")?;
+    let mut rows = stmt.query(params![target])?;
+    let mut files = HashMap::new();
+    while let Some(row) = rows.next()? {
+        files.insert(row.get("PATH")?, row.get("HASH")?);
+    }
+    Ok(files)
+}
+
+/// recording a file copied to a deploy target, or updating its hash
+pub fn update_deployed_file(
+    conn: &Connection,
+    target: &str,
+    path: &str,
+    hash: &str,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "INSERT or REPLACE INTO deployed\
+        (TARGET, PATH, HASH)\
+        VALUES (?1, ?2, ?3)",
+    )?;
+    stmt.execute(params![target, path, hash])?;
+    Ok(())
+}
+
+/// forgetting a file no longer generated
+pub fn remove_deployed_file(conn: &Connection, target: &str, path: &str) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare("DELETE FROM deployed WHERE TARGET = ?1 AND PATH = ?2")?;
+    stmt.execute(params![target, path])?;
+    Ok(())
+}
+
+/// replacing all the files on a deploy target, when another release is switched to
+pub fn replace_deployed_files(
+    conn: &Connection,
+    target: &str,
+    files: &HashMap<String, String>,
+) -> Result<(), err::Error> {
+    conn.execute("DELETE FROM deployed WHERE TARGET = ?1", params![target])?;
+    for (path, hash) in files {
+        update_deployed_file(conn, target, path, hash)?;
+    }
+    Ok(())
+}
+
+/// giving the deploys recorded before they were kept per target to a target
+/// only a target without deploys of its own takes them, and only the ones made with its method
+pub fn claim_untargeted(conn: &Connection, target: &str, method: &str) -> Result<(), err::Error> {
+    let owned: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM deploys WHERE TARGET = ?1) \
+        OR EXISTS (SELECT 1 FROM deployed WHERE TARGET = ?1)",
+        params![target],
+        |row| row.get(0),
+    )?;
+    // the files recorded are the ones of the last deploy
+    let last_method: Option<String> = conn
+        .query_row(
+            "SELECT METHOD FROM deploys WHERE TARGET = '' ORDER BY ID DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if owned || last_method.is_some_and(|m| m != method) {
+        return Ok(());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE deploys SET TARGET = ?1 WHERE TARGET = '' AND METHOD = ?2",
+        params![target, method],
+    )?;
+    tx.execute(
+        "UPDATE release_files SET TARGET = ?1 WHERE TARGET = '' \
+        AND RELEASE IN (SELECT RELEASE FROM deploys WHERE TARGET = ?1)",
+        params![target],
+    )?;
+    tx.execute(
+        "UPDATE deployed SET TARGET = ?1 WHERE TARGET = ''",
+        params![target],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// recording the summary of a deploy to a target
+pub fn insert_deploy(
+    conn: &Connection,
+    target: &str,
+    summary: &DeploySummary,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO deploys\
+        (TARGET, RELEASE, TIME, METHOD, ADDED, CHANGED, REMOVED, UNCHANGED, LIVE, PRUNED)\
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?;
+    stmt.execute(params![
+        target,
+        summary.release,
+        summary.time,
+        summary.method,
+        summary.added,
+        summary.changed,
+        summary.removed,
+        summary.unchanged,
+        summary.live,
+        summary.pruned
+    ])?;
+    Ok(())
+}
+
+/// getting the deploys to a target made as releases, the oldest first
+pub fn get_releases(conn: &Connection, target: &str) -> Result<Vec<DeploySummary>, err::Error> {
+    let mut stmt = conn.prepare(
+        "SELECT * FROM deploys WHERE TARGET = ?1 AND RELEASE IS NOT NULL ORDER BY ID",
+    )?;
+    let mut rows = stmt.query(params![target])?;
+    let mut releases = Vec::new();
+    while let Some(row) = rows.next()? {
+        releases.push(DeploySummary {
+            release: row.get("RELEASE")?,
+            time: row.get("TIME")?,
+            method: row.get("METHOD")?,
+            added: row.get("ADDED")?,
+            changed: row.get("CHANGED")?,
+            removed: row.get("REMOVED")?,
+            unchanged: row.get("UNCHANGED")?,
+            live: row.get("LIVE")?,
+            pruned: row.get("PRUNED")?,
+        });
+    }
+    Ok(releases)
+}
+
+/// marking a release as the one the site on a target points to
+pub fn set_live_release(conn: &Connection, target: &str, release: &str) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare("UPDATE deploys SET LIVE = (RELEASE IS ?2) WHERE TARGET = ?1")?;
+    stmt.execute(params![target, release])?;
+    Ok(())
+}
+
+/// recording the files in a release
+pub fn insert_release_files(
+    conn: &Connection,
+    target: &str,
+    release: &str,
+    files: &HashMap<String, String>,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "INSERT or REPLACE INTO release_files\
+        (TARGET, RELEASE, PATH, HASH)\
+        VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for (path, hash) in files {
+        stmt.execute(params![target, release, path, hash])?;
+    }
+    Ok(())
+}
+
+/// getting the files in a release, with the hashes of their contents
+pub fn get_release_files(
+    conn: &Connection,
+    target: &str,
+    release: &str,
+) -> Result<HashMap<String, String>, err::Error> {
+    let mut stmt =
+        conn.prepare("SELECT * FROM release_files WHERE TARGET = ?1 AND RELEASE = ?2")?;
+    let mut rows = stmt.query(params![target, release])?;
+    let mut files = HashMap::new();
+    while let Some(row) = rows.next()? {
+        files.insert(row.get("PATH")?, row.get("HASH")?);
+    }
+    Ok(files)
+}
+
+/// marking a release as removed from the target, forgetting its files
+pub fn prune_release(conn: &Connection, target: &str, release: &str) -> Result<(), err::Error> {
+    conn.execute(
+        "UPDATE deploys SET PRUNED = true WHERE TARGET = ?1 AND RELEASE = ?2",
+        params![target, release],
+    )?;
+    conn.execute(
+        "DELETE FROM release_files WHERE TARGET = ?1 AND RELEASE = ?2",
+        params![target, release],
+    )?;
+    Ok(())
+}
@@ -1,11 +1,38 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use log::info;
 use rusqlite::params;
-use rusqlite::Connection;
+use rusqlite::{Connection, ErrorCode, Row};
 
 use crate::api::err;
-use crate::api::folders::{ArticleFolder, ArticleFolderList, PictureFolder, PictureFolderList};
+use crate::api::folders::{
+    ArticleFolder, ArticleFolderList, PictureFolder, PictureFolderList, WatchMode,
+};
+use crate::model::articles::delete_articles_in;
+use crate::model::health::remove_folder_health;
+
+/// reading the watch mode of a folder, null for the one in the config
+fn watch_mode(row: &Row) -> Result<Option<WatchMode>, err::Error> {
+    match row.get::<&str, Option<String>>("WATCH")? {
+        Some(s) => Ok(Some(WatchMode::parse(&s)?)),
+        None => Ok(None),
+    }
+}
+
+fn article_folder(row: &Row) -> Result<ArticleFolder, err::Error> {
+    Ok(ArticleFolder {
+        path: PathBuf::from(row.get::<&str, String>("PATH")?),
+        deploy: row.get("DEPLOY")?,
+        need_confirm: row.get("CONFIRM")?,
+        watch: watch_mode(row)?,
+    })
+}
+
+fn picture_folder(row: &Row) -> Result<PictureFolder, err::Error> {
+    Ok(PictureFolder {
+        path: PathBuf::from(row.get::<&str, String>("PATH")?),
+        watch: watch_mode(row)?,
+    })
+}
 
 /// getting the article folders that should be monitored
 pub fn get_article_folders(conn: &Connection) -> Result<ArticleFolderList, err::Error> {
@@ -13,36 +40,75 @@ pub fn get_article_folders(conn: &Connection) -> Result<ArticleFolderList, err::
     let mut rows = stmt.query(params![])?;
     let mut folders = ArticleFolderList::new();
     while let Some(row) = rows.next()? {
-        folders.push(ArticleFolder {
-            path: PathBuf::from(row.get::<&str, String>("PATH")?),
-            deploy: row.get("DEPLOY")?,
-            need_confirm: row.get("CONFIRM")?,
-        })
+        folders.push(article_folder(row)?)
     }
     Ok(folders)
 }
 
+/// getting a single article folder by its path
+pub fn get_article_folder(
+    conn: &Connection,
+    p: &Path,
+) -> Result<Option<ArticleFolder>, err::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM article_folders WHERE PATH = ?1")?;
+    let mut rows = stmt.query(params![p.to_str()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(article_folder(row)?)),
+        None => Ok(None),
+    }
+}
+
 /// getting the picture folders that should be monitored
 pub fn get_picture_folders(conn: &Connection) -> Result<PictureFolderList, err::Error> {
     let mut stmt = conn.prepare("SELECT * FROM picture_folders")?;
     let mut rows = stmt.query(params![])?;
     let mut folders = PictureFolderList::new();
     while let Some(row) = rows.next()? {
-        folders.push(PictureFolder {
-            path: PathBuf::from(row.get::<&str, String>("PATH")?),
-        })
+        folders.push(picture_folder(row)?)
     }
     Ok(folders)
 }
 
+/// getting a single picture folder by its path
+pub fn get_picture_folder(
+    conn: &Connection,
+    p: &Path,
+) -> Result<Option<PictureFolder>, err::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM picture_folders WHERE PATH = ?1")?;
+    let mut rows = stmt.query(params![p.to_str()])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(picture_folder(row)?)),
+        None => Ok(None),
+    }
+}
+
+/// turning the violation of the unique PATH into a readable error
+fn duplicate_error(e: rusqlite::Error, p: &Path) -> err::Error {
+    match e {
+        rusqlite::Error::SqliteFailure(ref f, _) if f.code == ErrorCode::ConstraintViolation => {
+            err::Error::new(
+                err::Reason::Database,
+                format!("{:?} is already monitored", p),
+            )
+        }
+        e => e.into(),
+    }
+}
+
 /// adding an article folder that should be monitored
 pub fn add_article_folder(conn: &Connection, f: ArticleFolder) -> Result<(), err::Error> {
     let mut stmt = conn.prepare(
         "INSERT INTO article_folders\
-        (PATH, CONFIRM, DEPLOY)\
-        VALUES (?1, ?2, ?3)",
+        (PATH, CONFIRM, DEPLOY, WATCH)\
+        VALUES (?1, ?2, ?3, ?4)",
     )?;
-    stmt.execute(params![f.path.to_str(), f.need_confirm, f.deploy])?;
+    stmt.execute(params![
+        f.path.to_str(),
+        f.need_confirm,
+        f.deploy,
+        f.watch.map(|w| w.as_str())
+    ])
+    .map_err(|e| duplicate_error(e, &f.path))?;
     Ok(())
 }
 
@@ -50,33 +116,71 @@ pub fn add_article_folder(conn: &Connection, f: ArticleFolder) -> Result<(), err
 pub fn add_picture_folder(conn: &Connection, f: PictureFolder) -> Result<(), err::Error> {
     let mut stmt = conn.prepare(
         "INSERT INTO picture_folders\
-        (PATH)\
-        VALUES (?1)",
+        (PATH, WATCH)\
+        VALUES (?1, ?2)",
     )?;
-    stmt.execute(params![f.path.to_str()])?;
+    stmt.execute(params![f.path.to_str(), f.watch.map(|w| w.as_str())])
+        .map_err(|e| duplicate_error(e, &f.path))?;
     Ok(())
 }
 
-/// initializing folders
-pub fn init(conn: &Connection) -> Result<(), err::Error> {
-    info!("Initializing Folders Model");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS article_folders (\
-        ID      INTEGER     PRIMARY KEY AUTOINCREMENT,  \
-        PATH    TEXT        NOT NULL,\
-        DEPLOY  TEXT        NOT NULL,\
-        CONFIRM BOOLEAN     NOT NULL\
-        )",
-        [],
+/// updating the settings of an article folder, found by its path
+/// its articles are unpublished, and added again once the folder is rescanned
+/// returns the number of rows updated
+pub fn update_article_folder(conn: &Connection, f: ArticleFolder) -> Result<usize, err::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let updated = tx.execute(
+        "UPDATE article_folders SET CONFIRM = ?2, DEPLOY = ?3, WATCH = ?4 WHERE PATH = ?1",
+        params![
+            f.path.to_str(),
+            f.need_confirm,
+            f.deploy,
+            f.watch.map(|w| w.as_str())
+        ],
     )?;
+    if updated > 0 {
+        delete_articles_in(&tx, &f.path)?;
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// updating the settings of a picture folder, found by its path
+/// returns the number of rows updated
+pub fn update_picture_folder(conn: &Connection, f: PictureFolder) -> Result<usize, err::Error> {
+    Ok(conn.execute(
+        "UPDATE picture_folders SET WATCH = ?2 WHERE PATH = ?1",
+        params![f.path.to_str(), f.watch.map(|w| w.as_str())],
+    )?)
+}
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS picture_folders (\
-        ID      INTEGER     PRIMARY KEY AUTOINCREMENT,  \
-        PATH    TEXT        NOT NULL\
-        )",
-        [],
+/// removing an article folder and unpublishing its articles
+/// returns the number of rows deleted
+pub fn remove_article_folder(conn: &Connection, p: &Path) -> Result<usize, err::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let removed = tx.execute(
+        "DELETE FROM article_folders WHERE PATH = ?1",
+        params![p.to_str()],
     )?;
+    if removed > 0 {
+        delete_articles_in(&tx, p)?;
+        remove_folder_health(&tx, p)?;
+    }
+    tx.commit()?;
+    Ok(removed)
+}
 
-    Ok(())
+/// removing a picture folder, returns the number of rows deleted
+/// pictures already registered are kept
+pub fn remove_picture_folder(conn: &Connection, p: &Path) -> Result<usize, err::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let removed = tx.execute(
+        "DELETE FROM picture_folders WHERE PATH = ?1",
+        params![p.to_str()],
+    )?;
+    if removed > 0 {
+        remove_folder_health(&tx, p)?;
+    }
+    tx.commit()?;
+    Ok(removed)
 }
@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection};
+
+use crate::api::err;
+use crate::api::folders::{now, FolderHealth, WatcherState};
+
+/// recording a watcher started for a folder, replacing the record of the previous one
+/// the last event and error are kept
+pub fn start_watcher(conn: &Connection, path: &Path, watcher: i64) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "INSERT INTO folder_health\
+        (PATH, WATCHER, STATE, RESTARTS, SEEN)\
+        VALUES (?1, ?2, ?3, 0, ?4)\
+        ON CONFLICT(PATH) DO UPDATE SET \
+        WATCHER = ?2, STATE = ?3, RESTARTS = 0, SEEN = ?4",
+    )?;
+    stmt.execute(params![
+        path.to_str(),
+        watcher,
+        WatcherState::Running.as_str(),
+        now()
+    ])?;
+    Ok(())
+}
+
+/// changing the state of a watcher
+/// nothing is changed if another watcher of the folder is started since
+pub fn set_watcher_state(
+    conn: &Connection,
+    path: &Path,
+    watcher: i64,
+    state: WatcherState,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "UPDATE folder_health SET STATE = ?1, SEEN = ?2 WHERE PATH = ?3 AND WATCHER = ?4",
+    )?;
+    stmt.execute(params![state.as_str(), now(), path.to_str(), watcher])?;
+    Ok(())
+}
+
+/// recording a failed watcher waiting to be restarted
+pub fn restart_watcher(
+    conn: &Connection,
+    path: &Path,
+    watcher: i64,
+    error: &str,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "UPDATE folder_health SET \
+        STATE = ?1, RESTARTS = RESTARTS + 1, LAST_ERROR = ?2, ERROR_TIME = ?3, SEEN = ?3 \
+        WHERE PATH = ?4 AND WATCHER = ?5",
+    )?;
+    stmt.execute(params![
+        WatcherState::Restarting.as_str(),
+        error,
+        now(),
+        path.to_str(),
+        watcher
+    ])?;
+    Ok(())
+}
+
+/// recording an error a watcher keeps running after, such as an unreadable file
+pub fn record_folder_error(
+    conn: &Connection,
+    path: &Path,
+    watcher: i64,
+    error: &str,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "UPDATE folder_health SET LAST_ERROR = ?1, ERROR_TIME = ?2, SEEN = ?2 \
+        WHERE PATH = ?3 AND WATCHER = ?4",
+    )?;
+    stmt.execute(params![error, now(), path.to_str(), watcher])?;
+    Ok(())
+}
+
+/// recording a change handled by a watcher
+pub fn record_folder_event(conn: &Connection, path: &Path, watcher: i64) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "UPDATE folder_health SET LAST_EVENT = ?1, SEEN = ?1 WHERE PATH = ?2 AND WATCHER = ?3",
+    )?;
+    stmt.execute(params![now(), path.to_str(), watcher])?;
+    Ok(())
+}
+
+/// recording the watchers of some folders are alive
+/// nothing is changed for a folder if another watcher of it is started since
+pub fn see_watchers(conn: &Connection, watchers: &[(&Path, i64)]) -> Result<(), err::Error> {
+    let tx = conn.unchecked_transaction()?;
+    let time = now();
+    for (path, watcher) in watchers {
+        tx.execute(
+            "UPDATE folder_health SET SEEN = ?1 WHERE PATH = ?2 AND WATCHER = ?3 AND STATE != ?4",
+            params![time, path.to_str(), watcher, WatcherState::Stopped.as_str()],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// getting the health of the watcher of a folder, none if it has never been watched
+pub fn get_folder_health(
+    conn: &Connection,
+    path: &Path,
+) -> Result<Option<FolderHealth>, err::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM folder_health WHERE PATH = ?1")?;
+    let mut rows = stmt.query(params![path.to_str()])?;
+    let row = match rows.next()? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    Ok(Some(FolderHealth {
+        path: PathBuf::from(row.get::<&str, String>("PATH")?),
+        state: WatcherState::parse(&row.get::<&str, String>("STATE")?)?,
+        restarts: row.get("RESTARTS")?,
+        last_event: row.get("LAST_EVENT")?,
+        last_error: row.get("LAST_ERROR")?,
+        error_time: row.get("ERROR_TIME")?,
+        seen: row.get("SEEN")?,
+    }))
+}
+
+/// forgetting the health of a folder no longer monitored
+pub fn remove_folder_health(conn: &Connection, path: &Path) -> Result<(), err::Error> {
+    conn.execute(
+        "DELETE FROM folder_health WHERE PATH = ?1",
+        params![path.to_str()],
+    )?;
+    Ok(())
+}
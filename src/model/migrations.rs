@@ -0,0 +1,318 @@
+use log::info;
+use rusqlite::Connection;
+
+use crate::api::err;
+
+/// the schema changes in order, the database is at version i + 1 after MIGRATIONS[i]
+/// never edit a released one, append a new one instead
+const MIGRATIONS: [&str; 13] = [
+    // 1: the tables as they were before versioning
+    "CREATE TABLE IF NOT EXISTS article_folders (\
+        ID      INTEGER     PRIMARY KEY AUTOINCREMENT,  \
+        PATH    TEXT        NOT NULL,\
+        DEPLOY  TEXT        NOT NULL,\
+        CONFIRM BOOLEAN     NOT NULL\
+    );\
+    CREATE TABLE IF NOT EXISTS picture_folders (\
+        ID      INTEGER     PRIMARY KEY AUTOINCREMENT,  \
+        PATH    TEXT        NOT NULL\
+    );\
+    DELETE FROM article_folders WHERE ID NOT IN \
+        (SELECT MIN(ID) FROM article_folders GROUP BY PATH);\
+    DELETE FROM picture_folders WHERE ID NOT IN \
+        (SELECT MIN(ID) FROM picture_folders GROUP BY PATH);\
+    CREATE UNIQUE INDEX IF NOT EXISTS article_folders_path ON article_folders(PATH);\
+    CREATE UNIQUE INDEX IF NOT EXISTS picture_folders_path ON picture_folders(PATH);\
+    CREATE TABLE IF NOT EXISTS articles (\
+        PATH            TEXT        NOT NULL,\
+        DEPLOY_FOLDER   BOOLEAN     NOT NULL,\
+        TIME            INTEGER     NOT NULL,\
+        PRIMARY KEY(PATH, DEPLOY_FOLDER)\
+    );\
+    CREATE TABLE IF NOT EXISTS pictures (\
+        ID              INTEGER     PRIMARY KEY AUTOINCREMENT,  \
+        PATH            TEXT        NOT NULL,\
+        HASH            TEXT        NOT NULL,\
+        PHOTOGRAPHY     BOOLEAN     NOT NULL    DEFAULT false,\
+        HASH_OLD        TEXT,\
+        SELECTED        BOOLEAN     DEFAULT false,\
+        TITLE           TEXT,\
+        PARAMS          TEXT,\
+        DATE            TEXT,\
+        CAMERA          TEXT,\
+        DIRECTION       TEXT,\
+        ARTICLE         TEXT\
+    );",
+    // 2: articles.DEPLOY_FOLDER holds a path
+    "CREATE TABLE articles_new (\
+        PATH            TEXT        NOT NULL,\
+        DEPLOY_FOLDER   TEXT        NOT NULL,\
+        TIME            INTEGER     NOT NULL,\
+        PRIMARY KEY(PATH, DEPLOY_FOLDER)\
+    );\
+    INSERT INTO articles_new SELECT PATH, DEPLOY_FOLDER, TIME FROM articles;\
+    DROP TABLE articles;\
+    ALTER TABLE articles_new RENAME TO articles;",
+    // 3: the processed articles, so that only changed ones are processed again
+    "CREATE TABLE article_cache (\
+        PATH            TEXT        PRIMARY KEY,\
+        HASH            TEXT        NOT NULL,\
+        FRONT_MATTER    TEXT        NOT NULL,\
+        CONTENT         TEXT        NOT NULL,\
+        PICTURES        TEXT        NOT NULL\
+    );",
+    // 4: the files generated into the output folder, HASH is null once removed
+    "CREATE TABLE outputs (\
+        PATH            TEXT        PRIMARY KEY,\
+        HASH            TEXT\
+    );",
+    // 5: the uploads of the pictures, the ones registered before are already uploaded
+    "CREATE TABLE uploads (\
+        PATH            TEXT        PRIMARY KEY,\
+        STATUS          TEXT        NOT NULL,\
+        ATTEMPTS        INTEGER     NOT NULL    DEFAULT 0,\
+        ERROR           TEXT\
+    );\
+    INSERT OR IGNORE INTO uploads (PATH, STATUS) SELECT PATH, 'uploaded' FROM pictures;",
+    // 6: the files on the deploy target, and the summaries of the deploys
+    // the files removed from outputs but not yet from the target are kept with an empty hash
+    "CREATE TABLE deployed (\
+        PATH            TEXT        PRIMARY KEY,\
+        HASH            TEXT        NOT NULL\
+    );\
+    INSERT INTO deployed (PATH, HASH) SELECT PATH, '' FROM outputs WHERE HASH IS NULL;\
+    DELETE FROM outputs WHERE HASH IS NULL;\
+    CREATE TABLE deploys (\
+        ID              INTEGER     PRIMARY KEY AUTOINCREMENT,  \
+        TIME            INTEGER     NOT NULL,\
+        METHOD          TEXT        NOT NULL,\
+        ADDED           INTEGER     NOT NULL,\
+        CHANGED         INTEGER     NOT NULL,\
+        REMOVED         INTEGER     NOT NULL,\
+        UNCHANGED       INTEGER     NOT NULL\
+    );",
+    // 7: each deploy is a release on the target, with the files in it
+    "ALTER TABLE deploys ADD COLUMN RELEASE TEXT;\
+    ALTER TABLE deploys ADD COLUMN LIVE BOOLEAN NOT NULL DEFAULT false;\
+    ALTER TABLE deploys ADD COLUMN PRUNED BOOLEAN NOT NULL DEFAULT false;\
+    CREATE UNIQUE INDEX deploys_release ON deploys(RELEASE);\
+    CREATE TABLE release_files (\
+        RELEASE         TEXT        NOT NULL,\
+        PATH            TEXT        NOT NULL,\
+        HASH            TEXT        NOT NULL,\
+        PRIMARY KEY(RELEASE, PATH)\
+    );",
+    // 8: how each folder is watched, null for watch_mode in the config
+    "ALTER TABLE article_folders ADD COLUMN WATCH TEXT;\
+    ALTER TABLE picture_folders ADD COLUMN WATCH TEXT;",
+    // 9: how the watcher of each folder is doing, WATCHER being the run writing it
+    "CREATE TABLE IF NOT EXISTS folder_health (\
+        PATH        TEXT        PRIMARY KEY,\
+        WATCHER     INTEGER     NOT NULL,\
+        STATE       TEXT        NOT NULL,\
+        RESTARTS    INTEGER     NOT NULL    DEFAULT 0,\
+        LAST_EVENT  INTEGER,\
+        LAST_ERROR  TEXT,\
+        ERROR_TIME  INTEGER,\
+        SEEN        INTEGER     NOT NULL\
+    );",
+    // 10: the cached articles may hold passwords, which are no longer cached
+    "DELETE FROM article_cache;",
+    // 11: the deploys are kept per target, the ones before have an empty TARGET
+    "CREATE TABLE deployed_new (\
+        TARGET          TEXT        NOT NULL,\
+        PATH            TEXT        NOT NULL,\
+        HASH            TEXT        NOT NULL,\
+        PRIMARY KEY(TARGET, PATH)\
+    );\
+    INSERT INTO deployed_new (TARGET, PATH, HASH) SELECT '', PATH, HASH FROM deployed;\
+    DROP TABLE deployed;\
+    ALTER TABLE deployed_new RENAME TO deployed;\
+    ALTER TABLE deploys ADD COLUMN TARGET TEXT NOT NULL DEFAULT '';\
+    DROP INDEX deploys_release;\
+    CREATE UNIQUE INDEX deploys_release ON deploys(TARGET, RELEASE);\
+    CREATE TABLE release_files_new (\
+        TARGET          TEXT        NOT NULL,\
+        RELEASE         TEXT        NOT NULL,\
+        PATH            TEXT        NOT NULL,\
+        HASH            TEXT        NOT NULL,\
+        PRIMARY KEY(TARGET, RELEASE, PATH)\
+    );\
+    INSERT INTO release_files_new (TARGET, RELEASE, PATH, HASH) \
+        SELECT '', RELEASE, PATH, HASH FROM release_files;\
+    DROP TABLE release_files;\
+    ALTER TABLE release_files_new RENAME TO release_files;",
+    // 12: the preview server renders into its own folder, its outputs and cached articles are kept apart
+    "CREATE TABLE outputs_new (\
+        PREVIEW         BOOLEAN     NOT NULL,\
+        PATH            TEXT        NOT NULL,\
+        HASH            TEXT,\
+        PRIMARY KEY(PREVIEW, PATH)\
+    );\
+    INSERT INTO outputs_new (PREVIEW, PATH, HASH) SELECT false, PATH, HASH FROM outputs;\
+    DROP TABLE outputs;\
+    ALTER TABLE outputs_new RENAME TO outputs;\
+    CREATE TABLE article_cache_new (\
+        PREVIEW         BOOLEAN     NOT NULL,\
+        PATH            TEXT        NOT NULL,\
+        HASH            TEXT        NOT NULL,\
+        FRONT_MATTER    TEXT        NOT NULL,\
+        CONTENT         TEXT        NOT NULL,\
+        PICTURES        TEXT        NOT NULL,\
+        PRIMARY KEY(PREVIEW, PATH)\
+    );\
+    INSERT INTO article_cache_new (PREVIEW, PATH, HASH, FRONT_MATTER, CONTENT, PICTURES) \
+        SELECT false, PATH, HASH, FRONT_MATTER, CONTENT, PICTURES FROM article_cache;\
+    DROP TABLE article_cache;\
+    ALTER TABLE article_cache_new RENAME TO article_cache;",
+    // 13: the uploads are kept per target, the ones before have an empty TARGET
+    "CREATE TABLE uploads_new (\
+        TARGET          TEXT        NOT NULL,\
+        PATH            TEXT        NOT NULL,\
+        STATUS          TEXT        NOT NULL,\
+        ATTEMPTS        INTEGER     NOT NULL    DEFAULT 0,\
+        ERROR           TEXT,\
+        PRIMARY KEY(TARGET, PATH)\
+    );\
+    INSERT INTO uploads_new (TARGET, PATH, STATUS, ATTEMPTS, ERROR) \
+        SELECT '', PATH, STATUS, ATTEMPTS, ERROR FROM uploads ORDER BY ROWID;\
+    DROP TABLE uploads;\
+    ALTER TABLE uploads_new RENAME TO uploads;",
+];
+
+/// the version of the schema, kept in the user_version of sqlite
+fn version(conn: &Connection) -> Result<usize, err::Error> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(version as usize)
+}
+
+/// bringing the database to the latest version
+/// each migration runs in its own transaction together with the version bump
+pub fn migrate(conn: &mut Connection) -> Result<(), err::Error> {
+    let current = version(conn)?;
+    if current > MIGRATIONS.len() {
+        return Err(err::Error::new(
+            err::Reason::Database,
+            format!(
+                "database version {} is newer than the supported version {}",
+                current,
+                MIGRATIONS.len()
+            ),
+        ));
+    }
+
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(current) {
+        let target = i + 1;
+        info!("Migrating Database to Version {}", target);
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", target as i64)?;
+        tx.commit()?;
+    }
+
+    info!("Database at Version {}", MIGRATIONS.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the names of the tables in the database
+    fn tables(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        let names = stmt.query_map([], |row| row.get(0)).unwrap();
+        names.map(|n| n.unwrap()).collect()
+    }
+
+    /// bringing a new database to a version, without the later migrations
+    fn migrate_to(conn: &mut Connection, target: usize) {
+        for (i, sql) in MIGRATIONS.iter().enumerate().take(target) {
+            let tx = conn.transaction().unwrap();
+            tx.execute_batch(sql).unwrap();
+            tx.pragma_update(None, "user_version", (i + 1) as i64)
+                .unwrap();
+            tx.commit().unwrap();
+        }
+    }
+
+    #[test]
+    fn migrates_a_new_database_to_the_latest_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), MIGRATIONS.len());
+        for table in [
+            "article_folders",
+            "picture_folders",
+            "articles",
+            "pictures",
+            "article_cache",
+            "outputs",
+            "uploads",
+            "deployed",
+            "deploys",
+            "release_files",
+            "folder_health",
+        ] {
+            assert!(tables(&conn).contains(&table.to_string()), "{}", table);
+        }
+    }
+
+    #[test]
+    fn migrating_again_changes_nothing() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO article_folders (PATH, DEPLOY, CONFIRM) VALUES ('/a', 'article', false)",
+            [],
+        )
+        .unwrap();
+        let before = tables(&conn);
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), MIGRATIONS.len());
+        assert_eq!(tables(&conn), before);
+        let folders: i64 = conn
+            .query_row("SELECT COUNT(*) FROM article_folders", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(folders, 1);
+    }
+
+    #[test]
+    fn migrates_from_an_older_version_in_order() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // a file removed from the output folder but not yet from the target, before version 6
+        migrate_to(&mut conn, 5);
+        conn.execute(
+            "INSERT INTO outputs (PATH, HASH) VALUES ('old.html', NULL)",
+            [],
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), MIGRATIONS.len());
+        // moved into deployed by version 6, then kept without a target by version 11
+        let (target, hash): (String, String) = conn
+            .query_row(
+                "SELECT TARGET, HASH FROM deployed WHERE PATH = 'old.html'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((target.as_str(), hash.as_str()), ("", ""));
+        let outputs: i64 = conn
+            .query_row("SELECT COUNT(*) FROM outputs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(outputs, 0);
+    }
+
+    #[test]
+    fn refuses_a_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", (MIGRATIONS.len() + 1) as i64)
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
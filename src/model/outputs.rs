@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use rusqlite::{params, Connection};
+
+use crate::api::err;
+
+/// getting the files generated by the last render, with the hashes of their contents
+/// paths are relative to the output folder, the preview server keeps its own
+pub fn get_outputs(
+    conn: &Connection,
+    preview: bool,
+) -> Result<HashMap<String, String>, err::Error> {
+    let mut stmt = conn.prepare("SELECT * FROM outputs WHERE PREVIEW = ?1")?;
+    let mut rows = stmt.query(params![preview])?;
+    let mut outputs = HashMap::new();
+    while let Some(row) = rows.next()? {
+        outputs.insert(row.get("PATH")?, row.get("HASH")?);
+    }
+    Ok(outputs)
+}
+
+/// recording a generated file, or updating its hash
+pub fn update_output(
+    conn: &Connection,
+    preview: bool,
+    path: &str,
+    hash: &str,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "INSERT or REPLACE INTO outputs\
+        (PREVIEW, PATH, HASH)\
+        VALUES (?1, ?2, ?3)",
+    )?;
+    stmt.execute(params![preview, path, hash])?;
+    Ok(())
+}
+
+/// forgetting a file no longer generated
+pub fn remove_output(conn: &Connection, preview: bool, path: &str) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare("DELETE FROM outputs WHERE PREVIEW = ?1 AND PATH = ?2")?;
+    stmt.execute(params![preview, path])?;
+    Ok(())
+}
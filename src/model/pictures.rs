@@ -32,7 +32,7 @@ pub fn find_picture(conn: &Connection, p: &Picture) -> Result<Option<PathBuf>, e
 /// as the picture may already exist in the database
 pub fn insert_picture(conn: &Connection, p: &Picture) -> Result<PathBuf, err::Error> {
     // pictures not labeled as photoography can't be compressed
-    if let Some(path) = find_picture(conn, &p)? {
+    if let Some(path) = find_picture(conn, p)? {
         debug!("Pic already exists {:?}", p);
         return Ok(path);
     }
@@ -119,26 +119,3 @@ pub fn get_photography_pictures(conn: &Connection) -> Result<PPictureList, err::
     info!("Gotten {} pictures", pictures.len());
     Ok(pictures)
 }
-
-/// initializing pictures table
-pub fn init(conn: &Connection) -> Result<(), err::Error> {
-    info!("Initializing Picture Model");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS pictures (\
-        ID              INTEGER     PRIMARY KEY AUTOINCREMENT,  \
-        PATH            TEXT        NOT NULL,\
-        HASH            TEXT        NOT NULL,\
-        PHOTOGRAPHY     BOOLEAN     NOT NULL    DEFAULT false,\
-        HASH_OLD        TEXT,\
-        SELECTED        BOOLEAN     DEFAULT false,\
-        TITLE           TEXT,\
-        PARAMS          TEXT,\
-        DATE            TEXT,\
-        CAMERA          TEXT,\
-        DIRECTION       TEXT,\
-        ARTICLE         TEXT\
-        )",
-        [],
-    )?;
-    Ok(())
-}
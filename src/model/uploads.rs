@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::api::err;
+
+/// the state of a picture on the deploy target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadStatus {
+    Pending,
+    Uploaded,
+    Failed,
+}
+
+impl UploadStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            UploadStatus::Pending => "pending",
+            UploadStatus::Uploaded => "uploaded",
+            UploadStatus::Failed => "failed",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, err::Error> {
+        match s {
+            "pending" => Ok(UploadStatus::Pending),
+            "uploaded" => Ok(UploadStatus::Uploaded),
+            "failed" => Ok(UploadStatus::Failed),
+            _ => Err(err::Error::new(
+                err::Reason::Database,
+                format!("Unknown upload status {}", s),
+            )),
+        }
+    }
+}
+
+/// a picture to be uploaded, the path is the local one
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub path: PathBuf,
+    pub status: UploadStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+/// queueing a picture for uploading to a deploy target
+/// nothing is changed if it's already queued or uploaded
+pub fn queue_upload(conn: &Connection, target: &str, path: &Path) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "INSERT OR IGNORE INTO uploads\
+        (TARGET, PATH, STATUS)\
+        VALUES (?1, ?2, ?3)",
+    )?;
+    stmt.execute(params![
+        target,
+        path.to_str(),
+        UploadStatus::Pending.as_str()
+    ])?;
+    Ok(())
+}
+
+/// queueing the registered pictures never queued for a deploy target, e.g. a new one
+/// returns how many are queued
+pub fn queue_missing_uploads(conn: &Connection, target: &str) -> Result<usize, err::Error> {
+    Ok(conn.execute(
+        "INSERT OR IGNORE INTO uploads (TARGET, PATH, STATUS) \
+        SELECT ?1, PATH, ?2 FROM pictures ORDER BY ID",
+        params![target, UploadStatus::Pending.as_str()],
+    )?)
+}
+
+/// giving the uploads recorded before they were kept per target to the target deployed to last,
+/// as the pictures were uploaded wherever the site was deployed, or to any target if none was
+pub fn claim_untargeted_uploads(
+    conn: &Connection,
+    target: &str,
+    method: &str,
+) -> Result<(), err::Error> {
+    let last: Option<(String, String)> = conn
+        .query_row(
+            "SELECT TARGET, METHOD FROM deploys ORDER BY ID DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let ours = match last {
+        Some((t, m)) => t == target || (t.is_empty() && m == method),
+        None => true,
+    };
+    if ours {
+        // a picture may be queued for the target already, the row queued first is kept
+        conn.execute(
+            "UPDATE OR IGNORE uploads SET TARGET = ?1 WHERE TARGET = ''",
+            params![target],
+        )?;
+    }
+    Ok(())
+}
+
+/// getting the pictures not uploaded to a deploy target yet, the failed ones included,
+/// in the order they are queued
+pub fn get_unfinished_uploads(conn: &Connection, target: &str) -> Result<Vec<Upload>, err::Error> {
+    let mut stmt =
+        conn.prepare("SELECT * FROM uploads WHERE TARGET = ?1 AND STATUS != ?2 ORDER BY ROWID")?;
+    let mut rows = stmt.query(params![target, UploadStatus::Uploaded.as_str()])?;
+    let mut uploads = Vec::new();
+    while let Some(row) = rows.next()? {
+        uploads.push(Upload {
+            path: PathBuf::from(row.get::<&str, String>("PATH")?),
+            status: UploadStatus::parse(&row.get::<&str, String>("STATUS")?)?,
+            attempts: row.get("ATTEMPTS")?,
+            error: row.get("ERROR")?,
+        });
+    }
+    Ok(uploads)
+}
+
+/// marking a picture as uploaded to a deploy target
+pub fn mark_uploaded(conn: &Connection, target: &str, path: &Path) -> Result<(), err::Error> {
+    let mut stmt = conn
+        .prepare("UPDATE uploads SET STATUS = ?1, ERROR = NULL WHERE TARGET = ?2 AND PATH = ?3")?;
+    stmt.execute(params![
+        UploadStatus::Uploaded.as_str(),
+        target,
+        path.to_str()
+    ])?;
+    Ok(())
+}
+
+/// marking a picture as failed to upload to a deploy target, counting the attempt
+pub fn mark_failed(
+    conn: &Connection,
+    target: &str,
+    path: &Path,
+    error: &str,
+) -> Result<(), err::Error> {
+    let mut stmt = conn.prepare(
+        "UPDATE uploads SET STATUS = ?1, ATTEMPTS = ATTEMPTS + 1, ERROR = ?2 \
+        WHERE TARGET = ?3 AND PATH = ?4",
+    )?;
+    stmt.execute(params![
+        UploadStatus::Failed.as_str(),
+        error,
+        target,
+        path.to_str()
+    ])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::migrations::migrate;
+
+    fn paths(uploads: Vec<Upload>) -> Vec<PathBuf> {
+        uploads.into_iter().map(|u| u.path).collect()
+    }
+
+    #[test]
+    fn keeps_the_queue_per_target() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        for path in ["/pic/a.jpg", "/pic/b.jpg"] {
+            conn.execute(
+                "INSERT INTO pictures (PATH, HASH) VALUES (?1, '')",
+                params![path],
+            )
+            .unwrap();
+        }
+
+        queue_upload(&conn, "scp:prod:/web", Path::new("/pic/a.jpg")).unwrap();
+        mark_uploaded(&conn, "scp:prod:/web", Path::new("/pic/a.jpg")).unwrap();
+        queue_upload(&conn, "scp:prod:/web", Path::new("/pic/b.jpg")).unwrap();
+        mark_failed(&conn, "scp:prod:/web", Path::new("/pic/b.jpg"), "down").unwrap();
+        assert_eq!(
+            paths(get_unfinished_uploads(&conn, "scp:prod:/web").unwrap()),
+            vec![PathBuf::from("/pic/b.jpg")]
+        );
+
+        // uploaded to one target, a picture is still queued for another
+        assert!(get_unfinished_uploads(&conn, "local:/web")
+            .unwrap()
+            .is_empty());
+        assert_eq!(queue_missing_uploads(&conn, "local:/web").unwrap(), 2);
+        assert_eq!(queue_missing_uploads(&conn, "local:/web").unwrap(), 0);
+        assert_eq!(
+            paths(get_unfinished_uploads(&conn, "local:/web").unwrap()),
+            vec![PathBuf::from("/pic/a.jpg"), PathBuf::from("/pic/b.jpg")]
+        );
+        assert_eq!(queue_missing_uploads(&conn, "scp:prod:/web").unwrap(), 0);
+    }
+
+    #[test]
+    fn gives_the_uploads_before_targets_to_the_last_deployed() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        queue_upload(&conn, "", Path::new("/pic/a.jpg")).unwrap();
+        conn.execute(
+            "INSERT INTO deploys (TARGET, TIME, METHOD, ADDED, CHANGED, REMOVED, UNCHANGED) \
+            VALUES ('scp:prod:/web', 0, 'scp', 0, 0, 0, 0)",
+            [],
+        )
+        .unwrap();
+
+        claim_untargeted_uploads(&conn, "local:/web", "local").unwrap();
+        assert!(get_unfinished_uploads(&conn, "local:/web")
+            .unwrap()
+            .is_empty());
+        claim_untargeted_uploads(&conn, "scp:prod:/web", "scp").unwrap();
+        assert_eq!(
+            paths(get_unfinished_uploads(&conn, "scp:prod:/web").unwrap()),
+            vec![PathBuf::from("/pic/a.jpg")]
+        );
+    }
+}
@@ -2,19 +2,139 @@
  * Monitors of the folders
  */
 use crate::{
-    api::{err, sync::GlobalConnPool},
+    api::{
+        config::GlobalConfig,
+        err,
+        folders::{WatchMode, WatcherState},
+        sync::GlobalConnPool,
+    },
     model::folders::{get_article_folders, get_picture_folders},
 };
 
+use log::{info, warn};
+use notify::{Config, Event, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, sleep};
+use std::time::{Duration, SystemTime};
+
 mod article_folder;
+pub mod config_file;
+mod debounce;
 mod picture_folder;
+mod registry;
+mod supervisor;
+
+/// how often the folder tables are checked for added or removed folders
+const REGISTRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// how often a watcher checks whether it should stop
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 /// initializing notifier for article and picture folders
+/// the folders are watched as soon as they are added to or removed from the database
 pub fn init() -> Result<(), err::Error> {
+    let mut registry = registry::Registry::default();
+    sync(&mut registry)?;
+
+    thread::spawn(move || loop {
+        sleep(REGISTRY_INTERVAL);
+        if let Err(e) = sync(&mut registry) {
+            warn!("Error syncing watched folders, {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+fn sync(registry: &mut registry::Registry) -> Result<(), err::Error> {
+    let conn = GlobalConnPool::global().0.get()?;
+    registry.sync(&conn)
+}
+
+/// what to do with the error of a single file while handling a folder
+/// returning it fails the whole folder, and returning Ok skips the file
+type OnError<'a> = &'a dyn Fn(&Path, err::Error) -> Result<(), err::Error>;
+
+/// scanning all the monitored folders once, without watching them
+/// any file failing fails the scan
+pub fn scan() -> Result<(), err::Error> {
     let pool = GlobalConnPool::global().0.clone();
-    let a_folders = get_article_folders(&pool.get().unwrap())?;
-    let p_folders = get_picture_folders(&pool.get().unwrap())?;
-    article_folder::watch_folders(a_folders);
-    picture_folder::watch_folders(p_folders);
+    let conn = pool.get()?;
+    let fail = |_: &Path, e| Err(e);
+    for folder in get_article_folders(&conn)? {
+        article_folder::scan_folder(&folder, &pool, None, &fail)?;
+    }
+    for folder in get_picture_folders(&conn)? {
+        picture_folder::scan_folder(&folder, None, &fail)?;
+    }
     Ok(())
 }
+
+/// how a folder is watched, the mode and the interval of polling if it's polled
+type WatchSettings = (WatchMode, Option<Duration>);
+
+/// the settings a folder is watched with, as the config is now
+/// the mode is the one of the folder, or watch_mode in the config
+fn watch_settings(mode: Option<WatchMode>) -> WatchSettings {
+    let config = GlobalConfig::global();
+    let mode = mode.unwrap_or(config.watch_mode);
+    let interval = Duration::from_secs(config.watch_poll_interval);
+    (mode, (mode != WatchMode::Native).then_some(interval))
+}
+
+/// starting the watchers of a folder in a mode, sending their events to tx
+/// they stop once dropped
+fn watch(
+    path: &Path,
+    mode: Option<WatchMode>,
+    recursive: RecursiveMode,
+    tx: Sender<notify::Result<Event>>,
+) -> Result<Vec<Box<dyn Watcher>>, err::Error> {
+    let (mode, interval) = watch_settings(mode);
+    let mut watchers: Vec<Box<dyn Watcher>> = Vec::new();
+
+    if mode != WatchMode::Poll {
+        let mut watcher = RecommendedWatcher::new(tx.clone(), Config::default())?;
+        watcher.watch(path, recursive)?;
+        watchers.push(Box::new(watcher));
+    }
+    if let Some(interval) = interval {
+        let mut watcher = PollWatcher::new(tx, Config::default().with_poll_interval(interval))?;
+        watcher.watch(path, recursive)?;
+        watchers.push(Box::new(watcher));
+    }
+    Ok(watchers)
+}
+
+/// whether a file is modified since the given time, always true without one
+fn modified_since(p: &Path, since: Option<SystemTime>) -> bool {
+    let since = match since {
+        Some(since) => since,
+        None => return true,
+    };
+    match p.metadata().and_then(|m| m.modified()) {
+        Ok(modified) => modified >= since,
+        Err(_) => true,
+    }
+}
+
+/// waiting until a folder is reachable, such as an unmounted drive
+/// the watcher is recorded unreachable meanwhile
+/// returns false once the watcher should stop
+fn wait_reachable(path: &Path, stop: &AtomicBool, health: &supervisor::Health) -> bool {
+    if path.is_dir() {
+        return true;
+    }
+    warn!("Folder {:?} is unreachable, waiting for it", path);
+    health.unreachable();
+    while !path.is_dir() {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        sleep(STOP_CHECK_INTERVAL);
+    }
+    info!("Folder {:?} is reachable again", path);
+    health.set_state(WatcherState::Running);
+    true
+}
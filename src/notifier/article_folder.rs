@@ -1,72 +1,89 @@
 use crate::api::articles::ArticleInfo;
 use crate::api::err;
-use crate::api::folders::{ArticleFolder, ArticleFolderList};
+use crate::api::folders::ArticleFolder;
 use crate::api::sync::{ConnPool, GlobalConnPool, NeedPublish};
-use crate::model::articles::{delete_article, update_article};
+use crate::model::articles::{delete_article, get_articles_in, update_article};
+use crate::notifier::debounce::{Change, Debouncer};
+use crate::notifier::supervisor::{supervise, Health, Supervised};
+use crate::notifier::{modified_since, wait_reachable, watch, OnError};
 
-use log::info;
-use notify::event::{CreateKind, RemoveKind};
-use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use log::{info, warn};
+use notify::RecursiveMode;
 
-use std::ffi::OsString;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
 use std::fs::read_to_string;
-use std::path::PathBuf;
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-/// initializing notifier for article folders
-pub fn watch_folders(a_folders: ArticleFolderList) {
+/// starting a supervised notifier thread for an article folder, until stop is set
+pub fn watch_folder(folder: ArticleFolder, stop: Arc<AtomicBool>) -> Supervised {
     let pool = GlobalConnPool::global().0.clone();
-    for folder in a_folders {
-        let pool = pool.clone();
-        thread::spawn(|| {
-            if let Err(e) = watch_article_folder(folder, pool) {
-                println!("error: {:?}", e);
-            }
-        });
-    }
+    // kept across restarts, so that a restarted notifier publishes only for what it missed
+    let scanned = Cell::new(None);
+    supervise(folder.path.clone(), stop, move |health, stop| {
+        watch_article_folder(&folder, &pool, &scanned, stop, health)
+    })
 }
 
 /// notifier for a single article folder
-fn watch_article_folder(folder: ArticleFolder, pool: ConnPool) -> Result<(), err::Error> {
+/// scanned is when the folder was last scanned, none if never
+fn watch_article_folder(
+    folder: &ArticleFolder,
+    pool: &ConnPool,
+    scanned: &Cell<Option<SystemTime>>,
+    stop: &AtomicBool,
+    health: &Health,
+) -> Result<(), err::Error> {
     info!("Initializing Article Folder {:?}", &folder);
 
     let (tx, rx) = std::sync::mpsc::channel();
-    let files = folder.path.read_dir()?;
 
-    // all files exist at init will be added to the database
-    for file in files {
-        let file = file?;
-        add_article(file.path(), &folder, &pool)?;
+    // the articles are kept while the folder is unreachable, and reconciled once it's back
+    if !wait_reachable(&folder.path, stop, health) {
+        return Ok(());
     }
 
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-    watcher.watch(folder.path.as_ref(), RecursiveMode::NonRecursive)?; // article folders are monitored non-recursively
+    // article folders are monitored non-recursively
+    // the watcher starts before the scan, so that the changes meanwhile are handled after it
+    let _watchers = watch(&folder.path, folder.watch, RecursiveMode::NonRecursive, tx)?;
+
+    let skip = |p: &Path, e| health.skip(p, e);
+    let now = SystemTime::now();
+    let changed = scan_folder(folder, pool, scanned.get(), &skip)?;
+    scanned.set(Some(now));
+    if changed {
+        NeedPublish::global().set(true);
+    }
 
     info!("Monitoring Article Folder {:?}", &folder);
-    for res in rx {
-        let event = match res {
-            Ok(event) => event,
-            Err(e) => return Err(e.into()),
-        };
-
-        // notifiers' event classification seems not to work well.
-        // delete may not work, modifications may have wrong modifykind
-        match event.kind {
-            EventKind::Create(CreateKind::File) => {
-                add_article(event.paths[0].clone(), &folder, &pool)?;
-            }
-            EventKind::Modify(_) => {
-                if event.paths.len() == 1 {
-                    add_article(event.paths[0].clone(), &folder, &pool)?;
-                } else {
-                    remove_article(event.paths[0].clone(), &pool)?;
-                    add_article(event.paths[1].clone(), &folder, &pool)?;
+    let mut debouncer = Debouncer::new(rx);
+    while let Some(change) = debouncer.next(stop) {
+        match change? {
+            // the kinds of events are not reliable, e.g. a save may be a rename
+            // so each changed path is judged by the file as it is now
+            // a file failing, such as an unreadable one, is skipped
+            Change::Paths(paths) => {
+                health.event();
+                for path in paths {
+                    if let Err(e) = update_path(path.clone(), folder, pool) {
+                        skip(&path, e)?;
+                    }
                 }
             }
-            EventKind::Remove(RemoveKind::File) => {
-                remove_article(event.paths[0].clone(), &pool)?;
+            // only a change missed by the watcher leads to a publish
+            Change::Rescan => {
+                let now = SystemTime::now();
+                let changed = scan_folder(folder, pool, scanned.get(), &skip)?;
+                scanned.set(Some(now));
+                if !changed {
+                    continue;
+                }
+                info!("Missed Changes Found in Article Folder {:?}", folder.path);
             }
-            _ => {}
         }
 
         // currently, all actions in the monitored folder will lead to a new publish
@@ -74,46 +91,115 @@ fn watch_article_folder(folder: ArticleFolder, pool: ConnPool) -> Result<(), err
         signal.set(true);
     }
 
+    info!("Stopped Monitoring Article Folder {:?}", &folder);
     Ok(())
 }
 
+/// reconciling the articles of a folder with the files inside
+/// new ones are added and missing ones removed, nothing changes if the folder is unreachable
+/// returns whether any article is added, removed, or modified since the given time
+/// the article of a file failing is kept if on_error skips it
+pub fn scan_folder(
+    folder: &ArticleFolder,
+    pool: &ConnPool,
+    since: Option<SystemTime>,
+    on_error: OnError,
+) -> Result<bool, err::Error> {
+    info!("Scanning Article Folder {:?}", folder);
+    let files = match folder.path.read_dir() {
+        Ok(files) => files,
+        Err(e) => {
+            warn!(
+                "Article Folder {:?} is unreachable, its articles are kept. {}",
+                folder.path, e
+            );
+            return Ok(false);
+        }
+    };
+
+    let known: HashSet<PathBuf> = {
+        let conn = pool.get()?;
+        get_articles_in(&conn, &folder.path)?
+            .into_iter()
+            .map(|a| a.path)
+            .collect()
+    };
+    let mut changed = false;
+    let mut found = HashSet::new();
+    for file in files {
+        let path = file?.path();
+        match add_article(path.clone(), folder, pool) {
+            Ok(true) => {
+                changed |= !known.contains(&path) || modified_since(&path, since);
+                found.insert(path);
+            }
+            Ok(false) => {}
+            Err(e) => {
+                on_error(&path, e)?;
+                found.insert(path);
+            }
+        }
+    }
+
+    let conn = pool.get()?;
+    for path in known {
+        if !found.contains(&path) {
+            info!("Removing Missing Article {:?}", path);
+            delete_article(&conn, path)?;
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
 /// derterming whether it's markdown by comparing its extension
-fn is_markdown(p: &PathBuf) -> bool {
+fn is_markdown(p: &Path) -> bool {
     p.is_file() && p.extension().unwrap_or(&OsString::new()) == "md"
 }
 
-fn find_deploy_flag(path: &PathBuf) -> Result<bool, err::Error> {
+fn find_deploy_flag(path: &Path) -> Result<bool, err::Error> {
     Ok(read_to_string(path)?.find("deploy: true").is_some())
 }
 
 /// updating an article if necessary
-fn add_article(p: PathBuf, f: &ArticleFolder, pool: &ConnPool) -> Result<(), err::Error> {
+/// returns whether it's an article to publish
+fn add_article(p: PathBuf, f: &ArticleFolder, pool: &ConnPool) -> Result<bool, err::Error> {
     info!("Juding Article {:?}", p);
 
     // if you have soft-linked files or whatever (like onedrive)
     // two unique path for the same file may be captured
     if !p.starts_with(&f.path) {
-        return Ok(());
+        return Ok(false);
     };
 
     if !is_markdown(&p) {
-        return Ok(());
+        return Ok(false);
     };
     if f.need_confirm && !find_deploy_flag(&p)? {
-        return Ok(());
+        return Ok(false);
     };
 
     info!("Adding Article{:?}", p);
-    update_article(&pool.get().unwrap(), ArticleInfo::new(p, f.deploy.clone()))?;
+    let conn = pool.get()?;
+    update_article(&conn, ArticleInfo::new(p, f.deploy.clone()))?;
+    Ok(true)
+}
+
+/// adding or removing the article of a changed path, by the file as it is now
+fn update_path(p: PathBuf, f: &ArticleFolder, pool: &ConnPool) -> Result<(), err::Error> {
+    if !add_article(p.clone(), f, pool)? {
+        remove_article(p, pool)?;
+    }
     Ok(())
 }
 
-/// deleting an article if necessary. may not work properly
+/// deleting an article if necessary, such as a removed file or one no longer confirmed
 fn remove_article(p: PathBuf, pool: &ConnPool) -> Result<(), err::Error> {
-    if !is_markdown(&p) {
+    if p.extension() != Some(OsStr::new("md")) {
         return Ok(());
     };
     info!("Removing Article {:?}", p);
-    delete_article(&pool.get().unwrap(), p)?;
+    let conn = pool.get()?;
+    delete_article(&conn, p)?;
     Ok(())
 }
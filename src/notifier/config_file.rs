@@ -0,0 +1,93 @@
+use crate::api::config::GlobalConfig;
+use crate::api::err;
+use crate::api::sync::NeedPublish;
+
+use log::{info, warn};
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+/// editors usually write a file with several events, which are handled once
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// starting a notifier thread for the config files
+/// load reads the config again in the same way it was read at start
+pub fn watch_config<F>(load: F)
+where
+    F: Fn() -> Result<GlobalConfig, err::Error> + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(e) = watch_config_files(load) {
+            warn!("Error watching config files, {:?}", e);
+        }
+    });
+}
+
+fn watch_config_files<F>(load: F) -> Result<(), err::Error>
+where
+    F: Fn() -> Result<GlobalConfig, err::Error>,
+{
+    let sources = GlobalConfig::global().sources.clone();
+
+    // files are usually replaced when saved, so their folders are watched instead
+    let mut files = HashSet::new();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    for source in sources {
+        let source = source.canonicalize()?;
+        let dir = source.parent().unwrap().to_path_buf();
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        files.insert(source);
+    }
+    info!("Watching Config Files {:?}", files);
+
+    let is_source = |p: &PathBuf| files.contains(p);
+    while let Ok(res) = rx.recv() {
+        // a failed event is skipped, so that the config keeps being watched
+        let event = match res {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Error watching config files, {:?}", e);
+                continue;
+            }
+        };
+        if !event.paths.iter().any(is_source) {
+            continue;
+        }
+
+        thread::sleep(SETTLE_TIME);
+        while rx.try_recv().is_ok() {}
+
+        reload(&load);
+    }
+
+    Ok(())
+}
+
+/// swapping in the new config if it's valid, otherwise keeping the old one
+fn reload<F>(load: &F)
+where
+    F: Fn() -> Result<GlobalConfig, err::Error>,
+{
+    info!("Reloading Config");
+    let config = match load().and_then(|c| c.validate().map(|_| c)) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Config not reloaded, the old one is kept. {}", e);
+            return;
+        }
+    };
+
+    // these are only used when starting
+    let old = GlobalConfig::global();
+    if old.db_path != config.db_path || old.template_dir != config.template_dir {
+        warn!("Changes of db_path and template_dir take effect after restarting");
+    }
+
+    GlobalConfig::set(config);
+    NeedPublish::global().set(true);
+    info!("Config Reloaded");
+}
@@ -0,0 +1,211 @@
+use crate::api::config::GlobalConfig;
+use crate::api::err;
+
+use log::debug;
+use notify::{Event, EventKind};
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use super::STOP_CHECK_INTERVAL;
+
+/// names of the temp, swap and lock files written by editors and sync clients
+/// e.g. .~lock.a.md#, ~$a.md, .#a.md, #a.md#, a.md~, .a.md.swp, a.md.tmp
+const TEMP_PREFIXES: [&str; 3] = [".~", "~$", ".#"];
+const TEMP_SUFFIXES: [&str; 8] = [
+    "~",
+    ".swp",
+    ".swo",
+    ".swx",
+    ".tmp",
+    ".temp",
+    ".part",
+    ".crdownload",
+];
+
+/// whether a file is written by an editor or a sync client while saving
+pub fn is_temp_file(p: &Path) -> bool {
+    let name = p.file_name().and_then(OsStr::to_str).unwrap_or_default();
+    TEMP_PREFIXES.iter().any(|s| name.starts_with(s))
+        || TEMP_SUFFIXES.iter().any(|s| name.ends_with(s))
+        || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'))
+        // vim checks whether a folder is writable with this file
+        || name == "4913"
+}
+
+/// what a watcher should handle next
+pub enum Change {
+    /// the paths changed, in no particular order
+    Paths(Vec<PathBuf>),
+    /// the whole folder should be scanned again, in case any event is missed
+    Rescan,
+}
+
+/// collapsing the events of a watcher into the paths changed
+/// a burst of events on one path, such as an editor saving, is reported once
+/// after the path has been quiet for watch_debounce milliseconds
+/// a rescan is asked for every watch_rescan_interval seconds
+pub struct Debouncer {
+    rx: Receiver<notify::Result<Event>>,
+    pending: HashMap<PathBuf, Instant>,
+    scanned: Instant,
+}
+
+impl Debouncer {
+    pub fn new(rx: Receiver<notify::Result<Event>>) -> Self {
+        Debouncer {
+            rx,
+            pending: HashMap::new(),
+            scanned: Instant::now(),
+        }
+    }
+
+    /// waiting for the next change
+    /// returns None once the watcher should stop
+    pub fn next(&mut self, stop: &AtomicBool) -> Option<Result<Change, err::Error>> {
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            // these are read every time, as the config may be reloaded
+            let config = GlobalConfig::global();
+            let window = Duration::from_millis(config.watch_debounce);
+            let rescan = Duration::from_secs(config.watch_rescan_interval);
+            let now = Instant::now();
+            let ready = self.take_quiet(now, window);
+            if !ready.is_empty() {
+                return Some(Ok(Change::Paths(ready)));
+            }
+            if !rescan.is_zero() && self.pending.is_empty() && now >= self.scanned + rescan {
+                self.scanned = now;
+                return Some(Ok(Change::Rescan));
+            }
+
+            let timeout = self
+                .pending
+                .values()
+                .map(|last| (*last + window).saturating_duration_since(now))
+                .min()
+                .unwrap_or(STOP_CHECK_INTERVAL)
+                .min(STOP_CHECK_INTERVAL);
+            match self.rx.recv_timeout(timeout) {
+                Ok(Ok(event)) => self.add(event, Instant::now()),
+                Ok(Err(e)) => return Some(Err(e.into())),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+        }
+    }
+
+    /// taking the paths with no event for the window
+    fn take_quiet(&mut self, now: Instant, window: Duration) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+        self.pending.retain(|path, last| {
+            let quiet = now.duration_since(*last) >= window;
+            if quiet {
+                ready.push(path.clone());
+            }
+            !quiet
+        });
+        ready
+    }
+
+    /// recording the paths of an event received at now, the quiet time of each restarts
+    fn add(&mut self, event: Event, now: Instant) {
+        // reading a file changes nothing
+        if let EventKind::Access(_) = event.kind {
+            return;
+        }
+        for path in event.paths {
+            if is_temp_file(&path) {
+                debug!("Ignoring Temp File {:?}", path);
+                continue;
+            }
+            self.pending.insert(path, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> Event {
+        let mut event = Event::new(kind);
+        for path in paths {
+            event = event.add_path(PathBuf::from(path));
+        }
+        event
+    }
+
+    #[test]
+    fn temp_files_are_recognized() {
+        for name in [
+            ".~lock.a.md#",
+            "~$a.md",
+            ".#a.md",
+            "#a.md#",
+            "a.md~",
+            ".a.md.swp",
+            ".a.md.swo",
+            "a.md.tmp",
+            "a.jpg.part",
+            "a.jpg.crdownload",
+            "4913",
+        ] {
+            assert!(is_temp_file(&Path::new("/notes").join(name)), "{}", name);
+        }
+        for name in ["a.md", "#", "DEPLOY", "a#b.md", "tmp.md", "4913.md"] {
+            assert!(!is_temp_file(&Path::new("/notes").join(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn a_burst_of_events_is_reported_once_after_quiet() {
+        let (_tx, rx) = std::sync::mpsc::channel();
+        let mut debouncer = Debouncer::new(rx);
+        let window = Duration::from_millis(500);
+        let start = Instant::now();
+
+        let modify = |paths: &[&str]| event(EventKind::Modify(ModifyKind::Any), paths);
+        debouncer.add(
+            event(EventKind::Create(CreateKind::File), &["/n/a.md"]),
+            start,
+        );
+        debouncer.add(modify(&["/n/a.md", "/n/.a.md.swp"]), start);
+        debouncer.add(modify(&["/n/b.md"]), start + window / 2);
+        // each event restarts the quiet time of its path
+        debouncer.add(modify(&["/n/a.md"]), start + window / 2);
+        assert!(debouncer.take_quiet(start + window / 2, window).is_empty());
+
+        let mut ready = debouncer.take_quiet(start + window, window);
+        assert!(ready.is_empty(), "{:?}", ready);
+        ready = debouncer.take_quiet(start + window * 3 / 2, window);
+        ready.sort();
+        assert_eq!(
+            ready,
+            vec![PathBuf::from("/n/a.md"), PathBuf::from("/n/b.md")]
+        );
+        assert!(debouncer.take_quiet(start + window * 3, window).is_empty());
+    }
+
+    #[test]
+    fn reading_a_file_is_ignored() {
+        let (_tx, rx) = std::sync::mpsc::channel();
+        let mut debouncer = Debouncer::new(rx);
+        let start = Instant::now();
+        debouncer.add(
+            event(EventKind::Access(AccessKind::Any), &["/n/a.md"]),
+            start,
+        );
+        assert!(debouncer
+            .take_quiet(start + Duration::from_secs(1), Duration::ZERO)
+            .is_empty());
+    }
+}
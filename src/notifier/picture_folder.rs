@@ -1,127 +1,237 @@
 use crate::api::err;
-use crate::api::folders::{PictureFolder, PictureFolderList};
+use crate::api::folders::PictureFolder;
 use crate::api::pictures::PhotographyPicture;
 use crate::api::sync::NeedPublish;
+use crate::notifier::debounce::{Change, Debouncer};
+use crate::notifier::supervisor::{supervise, Health, Supervised};
+use crate::notifier::{modified_since, wait_reachable, watch, OnError};
 
 use log::info;
-use notify::event::CreateKind;
-use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::RecursiveMode;
 use regex::Regex;
 
+use std::cell::Cell;
 use std::ffi::OsStr;
 use std::fs::{read_to_string, DirEntry};
-use std::path::PathBuf;
-use std::thread;
-
-/// initializing notifier for picture folders
-pub fn watch_folders(p_folders: PictureFolderList) {
-    for folder in p_folders {
-        thread::spawn(|| {
-            if let Err(e) = watch_picture_folder(folder) {
-                println!("error: {:?}", e);
-            }
-        });
-    }
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// starting a supervised notifier thread for a picture folder, until stop is set
+pub fn watch_folder(folder: PictureFolder, stop: Arc<AtomicBool>) -> Supervised {
+    // kept across restarts, so that a restarted notifier searches only what it missed
+    let scanned = Cell::new(None);
+    supervise(folder.path.clone(), stop, move |health, stop| {
+        watch_picture_folder(&folder, &scanned, stop, health)
+    })
 }
 
 /// notifier for a single picture folder
-fn watch_picture_folder(folder: PictureFolder) -> Result<(), err::Error> {
+/// scanned is when the folder was last scanned, none if never
+fn watch_picture_folder(
+    folder: &PictureFolder,
+    scanned: &Cell<Option<SystemTime>>,
+    stop: &AtomicBool,
+    health: &Health,
+) -> Result<(), err::Error> {
     info!("Watching Picture Folder {:?}", folder);
 
     let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
-    watcher.watch(folder.path.as_ref(), RecursiveMode::Recursive)?; // picture folders are monitored recursively
 
-    for res in rx {
-        let event = match res {
-            Ok(event) => event,
-            Err(e) => return Err(e.into()),
-        };
+    if !wait_reachable(&folder.path, stop, health) {
+        return Ok(());
+    }
 
-        // Pictures are published only if a DEPLOY file is found in the same directory
-        // with the keyword DEPLOY inside the file
-        match event.kind {
-            EventKind::Create(CreateKind::File) => {
-                if is_deploy_file(&event.paths[0]) && find_deploy_flag(&event.paths[0])? {
-                    let path = event.paths[0].ancestors().next().unwrap().parent().unwrap();
-                    search_folder(path.to_path_buf())?;
-                } else {
-                    continue;
+    // picture folders are monitored recursively
+    // the watcher starts before the scan, so that the changes meanwhile are handled after it
+    let _watchers = watch(&folder.path, folder.watch, RecursiveMode::Recursive, tx)?;
+
+    // the DEPLOY files created or edited while arce was stopped are processed first
+    let skip = |p: &Path, e| health.skip(p, e);
+    let now = SystemTime::now();
+    let registered = scan_folder(folder, scanned.get(), &skip)?;
+    scanned.set(Some(now));
+    if registered > 0 {
+        NeedPublish::global().set(true);
+    }
+
+    let mut debouncer = Debouncer::new(rx);
+    while let Some(change) = debouncer.next(stop) {
+        let searched = match change? {
+            // Pictures are published only if a DEPLOY file is found in the same directory
+            // with the keyword DEPLOY inside the file
+            // a file failing, such as a broken picture, is skipped
+            Change::Paths(paths) => {
+                health.event();
+                let mut searched = false;
+                for path in paths {
+                    match search_deploy_file(&path, None, &skip) {
+                        Ok(found) => searched |= found.is_some(),
+                        Err(e) => skip(&path, e)?,
+                    }
                 }
+                searched
             }
-            EventKind::Modify(_) => {
-                if is_deploy_file(&event.paths[0]) && find_deploy_flag(&event.paths[0])? {
-                    let path = event.paths[0].ancestors().next().unwrap().parent().unwrap();
-                    search_folder(path.to_path_buf())?;
-                } else {
-                    continue;
+            // only pictures missed by the watcher lead to a publish
+            Change::Rescan if folder.path.is_dir() => {
+                let now = SystemTime::now();
+                let registered = scan_folder(folder, scanned.get(), &skip)?;
+                scanned.set(Some(now));
+                if registered > 0 {
+                    info!("Missed Pictures Found in Picture Folder {:?}", folder.path);
                 }
+                registered > 0
             }
-            _ => {
+            Change::Rescan => false,
+        };
+
+        // those unexpected modifications are filtered and don't lead to a publish
+        if searched {
+            NeedPublish::global().set(true);
+        }
+    }
+
+    info!("Stopped Watching Picture Folder {:?}", folder);
+    Ok(())
+}
+
+/// searching every directory with a DEPLOY file inside a picture folder, recursively
+/// only the pictures which may have changed since the given time are searched, see search_folder
+/// returns the number of pictures registered
+pub fn scan_folder(
+    folder: &PictureFolder,
+    since: Option<SystemTime>,
+    on_error: OnError,
+) -> Result<usize, err::Error> {
+    info!("Scanning Picture Folder {:?}", folder);
+    scan_dir(&folder.path, since, on_error)
+}
+
+fn scan_dir(p: &Path, since: Option<SystemTime>, on_error: OnError) -> Result<usize, err::Error> {
+    let mut registered = 0;
+    let deploy = p.join("DEPLOY");
+    match search_deploy_file(&deploy, since, on_error) {
+        Ok(found) => registered += found.unwrap_or(0),
+        Err(e) => on_error(&deploy, e)?,
+    }
+
+    // a directory failing to be read is skipped like a file, the rest of the folder is still scanned
+    let entries = match p.read_dir() {
+        Ok(entries) => entries,
+        Err(e) => {
+            on_error(p, e.into())?;
+            return Ok(registered);
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                on_error(p, e.into())?;
                 continue;
             }
+        };
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => registered += scan_dir(&entry.path(), since, on_error)?,
+            Ok(_) => {}
+            Err(e) => on_error(&entry.path(), e.into())?,
         }
-
-        // those unexpected modifications will be filtered and won't reach here
-        let signal = NeedPublish::global();
-        signal.set(true);
     }
+    Ok(registered)
+}
 
-    Ok(())
+/// searching the directory of a DEPLOY file for pictures
+/// returns the number of pictures registered, none if it's not a DEPLOY file to search
+fn search_deploy_file(
+    p: &Path,
+    since: Option<SystemTime>,
+    on_error: OnError,
+) -> Result<Option<usize>, err::Error> {
+    if !is_deploy_file(p) || !find_deploy_flag(p)? {
+        return Ok(None);
+    }
+    let dir = p.parent().unwrap().to_path_buf();
+    Ok(Some(search_folder(dir, since, on_error)?))
 }
 
 /// searching for pictures inside a folder
-fn search_folder(p: PathBuf) -> Result<(), err::Error> {
+/// with a time given, the pictures not modified since are skipped without being hashed
+/// unless a picture is moved in, or the DEPLOY file is changed, since
+/// returns the number of pictures registered
+fn search_folder(
+    p: PathBuf,
+    since: Option<SystemTime>,
+    on_error: OnError,
+) -> Result<usize, err::Error> {
     info!("Searching Picture Folder {:?}", p);
 
     // the deploy file also acts as the config/setting file
     let settings = read_to_string(p.join("DEPLOY")).unwrap_or_default();
+    // the folder is modified once a picture is moved in, keeping its own modified time
+    let since =
+        since.filter(|_| !modified_since(&p, since) && !modified_since(&p.join("DEPLOY"), since));
 
+    let mut registered = 0;
     let files = p.read_dir()?;
     for file in files {
         let file = file?;
         info!("Searching file{:?}", file);
 
-        if is_pic(&file.path()) && !search_flag("IGNORE", &file, &settings) {
-            let mut pic = PhotographyPicture::from_dir(
-                file.path(),
-                search_flag("SELECTED", &file, &settings),
-                search_text("LINK", &file, &settings),
-                search_text("TITLE", &file, &settings).unwrap_or(
-                    PathBuf::from(file.file_name())
-                        .file_stem()
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string(), // the default title is its file name ( without extension )
-                ),
-            )?;
-
-            info!("Getting Pic {:?}", pic);
-
-            // we don't need to do anything if it already exists in database
-            if pic.is_registered()? {
-                continue;
+        if is_pic(&file.path())
+            && !search_flag("IGNORE", &file, &settings)
+            && modified_since(&file.path(), since)
+        {
+            match register_picture(&file, &settings) {
+                Ok(true) => registered += 1,
+                Ok(false) => {}
+                Err(e) => on_error(&file.path(), e)?,
             }
+        }
+    }
+    Ok(registered)
+}
 
-            // read exif and copy it to the local picture folder
-            // it will be renamed, and compressed if necessary
-            pic = pic.read_info()?.process_and_store()?;
+/// registering a picture with the settings in the DEPLOY file
+/// returns false if it's already registered
+fn register_picture(file: &DirEntry, settings: &str) -> Result<bool, err::Error> {
+    let mut pic = PhotographyPicture::from_dir(
+        file.path(),
+        search_flag("SELECTED", file, settings),
+        search_text("LINK", file, settings),
+        search_text("TITLE", file, settings).unwrap_or(
+            PathBuf::from(file.file_name())
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(), // the default title is its file name ( without extension )
+        ),
+    )?;
 
-            // store its info in the database and upload it to the server using scp
-            pic.register_and_upload()?;
-        }
+    info!("Getting Pic {:?}", pic);
+
+    // we don't need to do anything if it already exists in database
+    if pic.is_registered()? {
+        return Ok(false);
     }
-    Ok(())
+
+    // read exif and copy it to the local picture folder
+    // it will be renamed, and compressed if necessary
+    pic = pic.read_info()?.process_and_store()?;
+
+    // store its info in the database, and queue it for uploading to the server
+    pic.register_and_queue()?;
+    Ok(true)
 }
 
 // Determine whether a file is a picture according to its extension
-fn is_pic(p: &PathBuf) -> bool {
+fn is_pic(p: &Path) -> bool {
     p.is_file()
-        && vec!["jpg", "jpeg", "png", "JPG", "PNG", "JPEG"]
+        && ["jpg", "jpeg", "png", "JPG", "PNG", "JPEG"]
             .contains(&p.extension().unwrap_or(OsStr::new("")).to_str().unwrap())
 }
 
-fn is_deploy_file(p: &PathBuf) -> bool {
+fn is_deploy_file(p: &Path) -> bool {
     p.is_file() && p.file_name() == Some(OsStr::new("DEPLOY"))
 }
 
@@ -139,7 +249,7 @@ fn search_text(flag: &str, file: &DirEntry, settings: &str) -> Option<String> {
     .unwrap();
 
     // return the first pattern found
-    for cap in re.captures_iter(settings) {
+    if let Some(cap) = re.captures_iter(settings).next() {
         return Some(cap[1].to_string());
     }
 
@@ -147,6 +257,6 @@ fn search_text(flag: &str, file: &DirEntry, settings: &str) -> Option<String> {
 }
 
 /// finding the DEPLOY keyword inside a file
-fn find_deploy_flag(path: &PathBuf) -> Result<bool, err::Error> {
+fn find_deploy_flag(path: &Path) -> Result<bool, err::Error> {
     Ok(read_to_string(path)?.find("DEPLOY").is_some())
 }
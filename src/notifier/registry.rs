@@ -0,0 +1,107 @@
+use crate::api::err;
+use crate::api::folders::{ArticleFolder, PictureFolder};
+use crate::model::folders::{get_article_folders, get_picture_folders};
+use crate::model::health::see_watchers;
+use crate::notifier::supervisor::Supervised;
+use crate::notifier::{article_folder, picture_folder, watch_settings, WatchSettings};
+
+use log::info;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// a running watcher thread, which is stopped and waited for once dropped
+/// so that a restarted watcher never runs alongside the old one
+struct Watch {
+    stop: Arc<AtomicBool>,
+    /// the id its health is recorded with
+    id: i64,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watch {
+    fn start(watch_folder: impl FnOnce(Arc<AtomicBool>) -> Supervised) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let Supervised { id, thread } = watch_folder(stop.clone());
+        Watch {
+            stop,
+            id,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The folders being watched, kept in line with the folder tables
+/// and with the settings they are watched with, which may come from the config
+#[derive(Default)]
+pub struct Registry {
+    articles: HashMap<PathBuf, (ArticleFolder, WatchSettings, Watch)>,
+    pictures: HashMap<PathBuf, (PictureFolder, WatchSettings, Watch)>,
+}
+
+impl Registry {
+    /// starting watchers for new folders, and stopping those of removed ones
+    /// a folder whose settings changed is restarted, once its old watcher is stopped
+    /// the watchers are recorded alive each time
+    pub fn sync(&mut self, conn: &Connection) -> Result<(), err::Error> {
+        let a_folders = get_article_folders(conn)?;
+        self.articles.retain(|_, (folder, settings, _)| {
+            let keep = a_folders.contains(folder) && *settings == watch_settings(folder.watch);
+            if !keep {
+                info!("Stop watching Article Folder {:?}", folder);
+            }
+            keep
+        });
+        for folder in a_folders {
+            if self.articles.contains_key(&folder.path) {
+                continue;
+            }
+            let settings = watch_settings(folder.watch);
+            let watch = Watch::start(|stop| article_folder::watch_folder(folder.clone(), stop));
+            self.articles
+                .insert(folder.path.clone(), (folder, settings, watch));
+        }
+
+        let p_folders = get_picture_folders(conn)?;
+        self.pictures.retain(|_, (folder, settings, _)| {
+            let keep = p_folders.contains(folder) && *settings == watch_settings(folder.watch);
+            if !keep {
+                info!("Stop watching Picture Folder {:?}", folder);
+            }
+            keep
+        });
+        for folder in p_folders {
+            if self.pictures.contains_key(&folder.path) {
+                continue;
+            }
+            let settings = watch_settings(folder.watch);
+            let watch = Watch::start(|stop| picture_folder::watch_folder(folder.clone(), stop));
+            self.pictures
+                .insert(folder.path.clone(), (folder, settings, watch));
+        }
+
+        let watchers: Vec<(&Path, i64)> = self
+            .articles
+            .iter()
+            .map(|(path, (_, _, watch))| (path.as_path(), watch.id))
+            .chain(
+                self.pictures
+                    .iter()
+                    .map(|(path, (_, _, watch))| (path.as_path(), watch.id)),
+            )
+            .collect();
+        see_watchers(conn, &watchers)
+    }
+}
@@ -0,0 +1,158 @@
+use crate::api::err;
+use crate::api::folders::WatcherState;
+use crate::api::sync::{ConnPool, GlobalConnPool};
+use crate::model::health::{
+    record_folder_error, record_folder_event, restart_watcher, set_watcher_state, start_watcher,
+};
+
+use log::{info, warn};
+use rusqlite::Connection;
+
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::STOP_CHECK_INTERVAL;
+
+/// the wait before restarting a failed watcher, doubled after each failure
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// the longest wait before restarting a failed watcher
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+/// a watcher running this long before failing is restarted without waiting long
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+/// numbering the watchers started by this process
+static WATCHERS: AtomicU32 = AtomicU32::new(0);
+
+/// the health record of a watcher in the database
+/// failing to update it is logged, and doesn't affect the watcher
+pub struct Health {
+    path: PathBuf,
+    /// unique among the processes watching the folder, so a stopped one can't overwrite a new one
+    id: i64,
+    pool: ConnPool,
+}
+
+impl Health {
+    fn new(path: PathBuf) -> Self {
+        let n = WATCHERS.fetch_add(1, Ordering::Relaxed);
+        Health {
+            path,
+            id: ((std::process::id() as i64) << 32) | n as i64,
+            pool: GlobalConnPool::global().0.clone(),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&Connection, &Path, i64) -> Result<(), err::Error>) {
+        let result = self
+            .pool
+            .get()
+            .map_err(err::Error::from)
+            .and_then(|conn| f(&conn, &self.path, self.id));
+        if let Err(e) = result {
+            warn!("Health of Folder {:?} not recorded. {}", self.path, e);
+        }
+    }
+
+    pub fn set_state(&self, state: WatcherState) {
+        self.update(|conn, path, id| set_watcher_state(conn, path, id, state));
+    }
+
+    /// recording the folder can't be reached, while the watcher waits for it
+    pub fn unreachable(&self) {
+        self.set_state(WatcherState::Unreachable);
+        self.update(|conn, path, id| record_folder_error(conn, path, id, "folder unreachable"));
+    }
+
+    /// recording a change handled
+    pub fn event(&self) {
+        self.update(record_folder_event);
+    }
+
+    /// skipping a file failing, the watcher keeps running and the error is recorded
+    pub fn skip(&self, p: &Path, e: err::Error) -> Result<(), err::Error> {
+        warn!("Skipping {:?}. {}", p, e);
+        self.update(|conn, path, id| record_folder_error(conn, path, id, &e.to_string()));
+        Ok(())
+    }
+}
+
+/// turning a panic into an error, so a panicked watcher is restarted like a failed one
+fn panic_error(panic: Box<dyn Any + Send>) -> err::Error {
+    let message = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    err::Error::new(err::Reason::Internal, format!("panicked, {}", message))
+}
+
+/// waiting before a restart, returns false once the watcher should stop
+fn wait(duration: Duration, stop: &AtomicBool) -> bool {
+    let until = Instant::now() + duration;
+    while Instant::now() < until {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        sleep(STOP_CHECK_INTERVAL.min(until.saturating_duration_since(Instant::now())));
+    }
+    !stop.load(Ordering::Relaxed)
+}
+
+/// a watcher thread started by supervise
+pub struct Supervised {
+    /// the id its health is recorded with
+    pub id: i64,
+    pub thread: JoinHandle<()>,
+}
+
+/// running the watcher of a folder in a thread until stop is set
+/// it's restarted with backoff whenever it fails or panics, and its health is recorded
+/// the watcher returns once stop is set, and so does the thread
+pub fn supervise<F>(path: PathBuf, stop: Arc<AtomicBool>, watcher: F) -> Supervised
+where
+    F: Fn(&Health, &AtomicBool) -> Result<(), err::Error> + Send + 'static,
+{
+    let health = Health::new(path);
+    let id = health.id;
+    let thread = thread::spawn(move || {
+        health.update(start_watcher);
+
+        let mut backoff = RESTART_BACKOFF;
+        loop {
+            let started = Instant::now();
+            let e = match catch_unwind(AssertUnwindSafe(|| watcher(&health, &stop))) {
+                Ok(Ok(())) => break,
+                Ok(Err(e)) => e,
+                Err(panic) => panic_error(panic),
+            };
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            if started.elapsed() >= HEALTHY_AFTER {
+                backoff = RESTART_BACKOFF;
+            }
+            warn!(
+                "Watcher of Folder {:?} failed, restarting in {:?}. {}",
+                health.path, backoff, e
+            );
+            health.update(|conn, path, id| restart_watcher(conn, path, id, &e.to_string()));
+            if !wait(backoff, &stop) {
+                break;
+            }
+            backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+
+            info!("Restarting Watcher of Folder {:?}", health.path);
+            health.set_state(WatcherState::Running);
+        }
+        health.set_state(WatcherState::Stopped);
+    });
+    Supervised { id, thread }
+}
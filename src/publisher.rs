@@ -1,11 +1,14 @@
-mod deployer;
+pub mod deployer;
+mod manifest;
 mod markdown;
 mod renderer;
 mod encrypter;
+pub mod uploader;
 
 use log::{info, warn};
 use std::{
-    fs::{copy, create_dir_all},
+    fs::{copy, create_dir_all, hard_link, remove_dir_all, remove_file, rename},
+    path::{Path, PathBuf},
     thread::{self, sleep},
     time::Duration,
 };
@@ -15,56 +18,226 @@ use crate::{
         config::GlobalConfig,
         err,
         pictures::PhotographyPictureBrief,
-        sync::{GlobalConnPool, NeedPublish},
+        sync::{GlobalConnPool, NeedPublish, Rendered},
     },
     model::pictures::get_photography_pictures,
 };
 
-const FOLDERS: [&str; 5] = [
-    "public/index",
-    "public/gallery",
-    "public/css",
-    "public/picture",
-    "public/category",
-];
+/// folders inside the output folder
+const FOLDERS: [&str; 4] = ["index", "gallery", "picture", "category"];
 
-/// initialize the folders
-fn init() -> Result<(), err::Error> {
+/// suffixes of the folders next to the output folder, used while building
+const STAGING_SUFFIX: &str = ".staging";
+const OLD_SUFFIX: &str = ".old";
+/// the suffix of the folder the preview server renders into
+pub const PREVIEW_SUFFIX: &str = ".preview";
+
+/// copy a folder recursively
+/// existing files are replaced instead of written into, as they may be linked to the live output
+fn copy_dir(from: &Path, to: &Path) -> Result<(), err::Error> {
+    create_dir_all(to)?;
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let dst = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dst)?;
+        } else {
+            if dst.is_file() {
+                remove_file(&dst)?;
+            }
+            copy(entry.path(), dst)?;
+        }
+    }
+    Ok(())
+}
+
+/// link a folder recursively, files are copied if they can't be linked
+fn link_dir(from: &Path, to: &Path) -> Result<(), err::Error> {
+    create_dir_all(to)?;
+    for entry in from.read_dir()? {
+        let entry = entry?;
+        let dst = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_dir(&entry.path(), &dst)?;
+        } else if hard_link(entry.path(), &dst).is_err() {
+            copy(entry.path(), dst)?;
+        }
+    }
+    Ok(())
+}
+
+/// a folder next to the output folder, e.g. public.staging for public
+pub fn sibling(output: &Path, suffix: &str) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    output.with_file_name(name)
+}
+
+/// preparing a staging folder holding the current output, to render into
+fn prepare_staging(config: &GlobalConfig) -> Result<PathBuf, err::Error> {
+    let staging = sibling(&config.output_dir, STAGING_SUFFIX);
+    if staging.exists() {
+        remove_dir_all(&staging)?;
+    }
+
+    // the unchanged files are linked, so that they are not rendered again
+    if config.output_dir.is_dir() {
+        link_dir(&config.output_dir, &staging)?;
+    }
     for folder in FOLDERS {
-        create_dir_all(folder)?;
+        create_dir_all(staging.join(folder))?;
+    }
+
+    // static folders keep their names inside the output folder, e.g. css/ to public/css/
+    for dir in &config.static_dirs {
+        let name = dir.file_name().ok_or_else(|| {
+            err::Error::new(
+                err::Reason::Filesystem,
+                format!("Invalid static folder {:?}", dir),
+            )
+        })?;
+        copy_dir(dir, &staging.join(name))?;
+    }
+
+    Ok(staging)
+}
+
+/// exchanging two folders at once with renameat2, so that neither path is ever missing
+/// returns false if it's not supported, e.g. by the filesystem
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> Result<bool, err::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = |p: &Path| {
+        CString::new(p.as_os_str().as_bytes())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+    };
+    let (a, b) = (path(a)?, path(b)?);
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::ENOSYS) | Some(libc::EINVAL) => Ok(false),
+        _ => Err(e.into()),
     }
+}
 
-    copy("css/main.css", "public/css/main.css")?;
-    copy("css/typora.css", "public/css/typora.css")?;
+/// exchanging two folders at once is only done on linux
+#[cfg(not(target_os = "linux"))]
+fn exchange(_: &Path, _: &Path) -> Result<bool, err::Error> {
+    Ok(false)
+}
 
+/// swapping the staging folder into the place of the output folder
+/// on linux both are exchanged at once, elsewhere the output is moved aside first,
+/// so it's missing until the staging folder is moved in, and restored by the next build if interrupted
+/// the old output is put back if the staging folder can't be moved in
+fn swap_staging(config: &GlobalConfig, staging: &Path) -> Result<(), err::Error> {
+    let old = sibling(&config.output_dir, OLD_SUFFIX);
+    if old.exists() {
+        if config.output_dir.exists() {
+            remove_dir_all(&old)?;
+        } else {
+            // left by a swap interrupted between the renames
+            warn!("Restoring the output folder from {:?}", old);
+            rename(&old, &config.output_dir)?;
+        }
+    }
+    if config.output_dir.exists() {
+        if exchange(staging, &config.output_dir)? {
+            // the staging folder holds the old output now, it's removed by the next build otherwise
+            if let Err(e) = remove_dir_all(staging) {
+                warn!("Error removing the old output {:?}, {:?}", staging, e);
+            }
+            return Ok(());
+        }
+        rename(&config.output_dir, &old)?;
+    }
+    if let Err(e) = rename(staging, &config.output_dir) {
+        if old.exists() {
+            rename(&old, &config.output_dir)?;
+        }
+        return Err(e.into());
+    }
+    if old.exists() {
+        remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+/// initialize the publisher
+pub fn init() -> Result<(), err::Error> {
     let names: Vec<_> = renderer::TERA.get_template_names().collect();
     info!("Parsed {} Templates: {:?}", names.len(), names);
 
     Ok(())
 }
 
-/// start publishing
-fn publish() -> Result<(), err::Error> {
-    info!("Start publishing");
+/// render the whole site into the output folder, without deploying it
+/// the site is rendered into a staging folder, and swapped in only if every step succeeds
+/// in strict mode, any article failing to render fails the build
+pub fn build(strict: bool) -> Result<(), err::Error> {
+    info!("Start building");
+    let config = GlobalConfig::global();
     let conn = GlobalConnPool::global().0.get().unwrap();
 
     // get the articles
-    let articles = markdown::process_articles()?;
+    let articles = markdown::process_articles(strict)?;
 
     // get the pictures
     let mut pictures = get_photography_pictures(&conn)?;
-    pictures.sort_by(|a, b| (&b).date.cmp(&a.date));
+    pictures.sort_by(|a, b| b.date.cmp(&a.date));
     let pictures: Vec<PhotographyPictureBrief> = pictures
         .into_iter()
-        .map(|p| PhotographyPictureBrief::from(p))
+        .map(PhotographyPictureBrief::from)
         .collect();
 
     // render the html
-    renderer::render(articles, pictures)?;
+    let staging = prepare_staging(&config)?;
+    let manifest = match renderer::render(&staging, articles, pictures) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            remove_dir_all(&staging)?;
+            return Err(e);
+        }
+    };
+    swap_staging(&config, &staging)?;
+    manifest.save(&conn)?;
+    Rendered::global().bump();
+
+    info!("Built");
+    Ok(())
+}
 
-    // deploy the pages
-    deployer::deploy();
+/// deploy the rendered pages
+/// the queued pictures are uploaded first, the pages are not deployed if any of them fails
+pub fn deploy() -> Result<(), err::Error> {
+    uploader::flush(deployer::deploy)
+}
 
+/// switch the site back to an earlier release, see deployer::rollback
+/// no picture is uploaded while switching
+pub fn rollback(release: Option<&str>) -> Result<String, err::Error> {
+    uploader::locked(|| deployer::rollback(release))
+}
+
+/// start publishing
+fn publish() -> Result<(), err::Error> {
+    info!("Start publishing");
+    build(false)?;
+    deploy()?;
     info!("Published");
     Ok(())
 }
@@ -72,13 +245,16 @@ fn publish() -> Result<(), err::Error> {
 /// start publisher thread
 pub fn start() {
     init().expect("Error initializing publisher");
-
-    let config = GlobalConfig::global();
-    if !config.deploy_auto {
-        return;
-    }
+    uploader::start();
 
     thread::spawn(|| loop {
+        // the config is read every time, as it may be reloaded
+        let config = GlobalConfig::global();
+        if !config.deploy_auto {
+            sleep(Duration::from_secs(1));
+            continue;
+        }
+
         // Timer
         sleep(Duration::new(config.deploy_interval.unwrap(), 0));
 
@@ -97,3 +273,44 @@ pub fn start() {
         NeedPublish::global().set(false);
     });
 }
+
+/// start a publisher thread which renders without deploying, used by the preview server
+pub fn start_preview() {
+    init().expect("Error initializing publisher");
+
+    thread::spawn(|| loop {
+        sleep(Duration::from_millis(500));
+
+        if !NeedPublish::global().get() {
+            continue;
+        };
+
+        if let Err(e) = build(false) {
+            warn!("Error building , {:?}", e);
+            continue;
+        }
+
+        NeedPublish::global().set(false);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchanges_folders_at_once() {
+        let root = std::env::temp_dir().join(format!("arce-publisher-{}", std::process::id()));
+        let (a, b) = (root.join("a"), root.join("b"));
+        create_dir_all(&a).unwrap();
+        create_dir_all(&b).unwrap();
+        std::fs::write(a.join("a.html"), "a").unwrap();
+        std::fs::write(b.join("b.html"), "b").unwrap();
+
+        let exchanged = exchange(&a, &b).unwrap();
+        assert_eq!(exchanged, cfg!(target_os = "linux"));
+        assert_eq!(a.join("b.html").is_file(), exchanged);
+        assert_eq!(b.join("a.html").is_file(), exchanged);
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,22 +1,469 @@
-use crate::api::config::GlobalConfig;
+/*
+ * Deploying the site and the pictures, to the target set in the config
+ */
+use crate::api::config::{DeployConfig, GlobalConfig};
+use crate::api::deploys::{Changes, DeploySummary};
+use crate::api::err;
+use crate::api::sync::GlobalConnPool;
+use crate::model::deploys::{
+    claim_untargeted, get_deployed_files, get_release_files, get_releases, insert_deploy,
+    insert_release_files, prune_release, remove_deployed_file, replace_deployed_files,
+    set_live_release, update_deployed_file,
+};
+use crate::model::uploads::claim_untargeted_uploads;
 use log::{info, warn};
-use std::process::Command;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-/// deploy the site by copying the files to the server
-pub fn deploy() {
+mod git;
+mod local;
+mod release;
+mod rsync;
+mod scp;
+
+/// a deploy target
+/// each deploy is a release, a copy of the live one with the changes applied
+pub trait Deployer {
+    /// creating a release, without making it live yet
+    /// paths are relative to the output folder, and the root of the site
+    fn deploy_release(
+        &self,
+        output: &Path,
+        changes: &Changes,
+        release: &str,
+    ) -> Result<(), err::Error>;
+
+    /// making a release live
+    fn switch_release(&self, release: &str) -> Result<(), err::Error>;
+
+    /// removing old releases from the target
+    fn remove_releases(&self, releases: &[String]) -> Result<(), err::Error>;
+
+    /// uploading a picture into the picture folder
+    fn upload_picture(&self, picture: &Path) -> Result<(), err::Error>;
+}
+
+/// the deployer of the target set in the config
+pub fn deployer(config: &GlobalConfig) -> Box<dyn Deployer> {
+    match config.deploy_config() {
+        DeployConfig::Scp {
+            server,
+            web_path,
+            pic_path,
+        } => Box::new(scp::Scp {
+            server,
+            web_path,
+            pic_path,
+        }),
+        DeployConfig::Rsync {
+            server,
+            web_path,
+            pic_path,
+        } => Box::new(rsync::Rsync {
+            server,
+            web_path,
+            pic_path,
+        }),
+        DeployConfig::Local { web_path, pic_path } => Box::new(local::Local { web_path, pic_path }),
+        DeployConfig::Git {
+            remote,
+            branch,
+            pic_path,
+            work_dir,
+        } => Box::new(git::Git {
+            remote,
+            branch,
+            pic_path,
+            work_dir: work_dir
+                .unwrap_or_else(|| super::sibling(&config.output_dir, git::WORK_DIR_SUFFIX)),
+        }),
+    }
+}
+
+/// how many times a deploy step is tried before giving up
+const ATTEMPTS: u32 = 3;
+
+/// the wait before the first retry, doubled after each one
+const BACKOFF: Duration = Duration::from_secs(2);
+
+/// running a command, returns its output
+/// fails if it can't be run or exits unsuccessfully, with its stderr as the message
+fn run(cmd: &mut Command) -> Result<Output, err::Error> {
+    info!("Running {:?}", cmd);
+    let output = cmd.output().map_err(|e| {
+        err::Error::new(
+            err::Reason::Deploy,
+            format!("{:?} can't be run: {}", cmd.get_program(), e),
+        )
+    })?;
+    if !output.status.success() {
+        return Err(err::Error::new(
+            err::Reason::Deploy,
+            format!(
+                "{:?} {}: {}",
+                cmd.get_program(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(output)
+}
+
+/// quoting an argument for the remote shell
+fn shell_quote(s: &str) -> String {
+    String::from("'") + &s.replace('\'', "'\\''") + "'"
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// copying files of the output folder into a local folder
+/// existing files are replaced instead of written into, as they may be linked to another release
+fn copy_files(output: &Path, paths: &[String], to: &Path) -> Result<(), err::Error> {
+    remove_files(paths, to)?;
+    for path in paths {
+        let dst = to.join(path);
+        std::fs::create_dir_all(dst.parent().unwrap())?;
+        std::fs::copy(output.join(path), dst)?;
+    }
+    Ok(())
+}
+
+/// removing files from a local folder, the missing ones are ignored
+fn remove_files(paths: &[String], from: &Path) -> Result<(), err::Error> {
+    for path in paths {
+        match std::fs::remove_file(from.join(path)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// hashing every file inside a folder recursively, keyed by the path relative to root
+fn hash_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut HashMap<String, String>,
+) -> Result<(), err::Error> {
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            hash_files(root, &entry.path(), files)?;
+            continue;
+        }
+        let path = entry.path();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        files.insert(relative, sha256::digest(&*std::fs::read(&path)?));
+    }
+    Ok(())
+}
+
+/// trying a deploy step a few times, waiting longer after each failure
+fn retry<T>(step: &str, f: impl Fn() -> Result<T, err::Error>) -> Result<T, err::Error> {
+    let mut wait = BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(t) => return Ok(t),
+            Err(e) if attempt < ATTEMPTS => {
+                warn!(
+                    "{} failed ({}/{}), retrying in {:?}. {}",
+                    step, attempt, ATTEMPTS, wait, e
+                );
+                sleep(wait);
+                wait *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                return Err(err::Error::new(
+                    err::Reason::Deploy,
+                    format!("{} failed {} times, {}", step, ATTEMPTS, e.message),
+                ))
+            }
+        }
+    }
+}
+
+/// the name of a new release, the time it's deployed
+fn release_name(time: u64, releases: &[DeploySummary]) -> String {
+    let taken = |name: &str| releases.iter().any(|r| r.release.as_deref() == Some(name));
+    let mut name = time.to_string();
+    let mut n = 1;
+    while taken(&name) {
+        n += 1;
+        name = format!("{}-{}", time, n);
+    }
+    name
+}
+
+/// the target set in the config
+/// it takes the deploys recorded before they were kept per target, if it has none
+/// and the uploads recorded before, if it's the one deployed to last
+pub fn current_target(conn: &Connection, config: &GlobalConfig) -> Result<String, err::Error> {
+    let deploy = config.deploy_config();
+    let target = deploy.target();
+    claim_untargeted(conn, &target, deploy.method())?;
+    claim_untargeted_uploads(conn, &target, deploy.method())?;
+    Ok(target)
+}
+
+/// the releases on the target set in the config, the oldest first
+pub fn releases() -> Result<Vec<DeploySummary>, err::Error> {
+    let conn = GlobalConnPool::global().0.get()?;
+    let target = current_target(&conn, &GlobalConfig::global())?;
+    get_releases(&conn, &target)
+}
+
+/// removing the releases beyond deploy_keep_releases from the target, the live one is always kept
+/// failing to remove them doesn't fail the deploy
+fn prune_releases(deployer: &dyn Deployer, target: &str) -> Result<(), err::Error> {
+    let config = GlobalConfig::global();
+    let conn = GlobalConnPool::global().0.get()?;
+    let stale: Vec<String> = get_releases(&conn, target)?
+        .into_iter()
+        .rev()
+        .filter(|r| !r.pruned)
+        .skip(config.deploy_keep_releases)
+        .filter(|r| !r.live)
+        .filter_map(|r| r.release)
+        .collect();
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    info!("Removing {} Old Release(s)", stale.len());
+    if let Err(e) = deployer.remove_releases(&stale) {
+        warn!("Old releases not removed. {}", e);
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    for release in &stale {
+        prune_release(&tx, target, release)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// comparing the files of the output folder with the deployed ones, both with their hashes
+/// returns the changes to transfer, counted into the summary
+/// and the files no longer generated, which are removed from the target only if delete is set
+fn diff(
+    current: &HashMap<String, String>,
+    deployed: &HashMap<String, String>,
+    delete: bool,
+    summary: &mut DeploySummary,
+) -> (Changes, Vec<String>) {
+    let mut changes = Changes::default();
+    for (path, hash) in current {
+        match deployed.get(path) {
+            None => summary.added += 1,
+            Some(h) if h != hash => summary.changed += 1,
+            _ => {
+                summary.unchanged += 1;
+                continue;
+            }
+        }
+        changes.copied.push(path.clone());
+    }
+    let mut removed: Vec<String> = deployed
+        .keys()
+        .filter(|path| !current.contains_key(*path))
+        .cloned()
+        .collect();
+    removed.sort();
+    if delete {
+        changes.removed = removed.clone();
+        summary.removed = removed.len();
+    }
+    changes.copied.sort();
+    (changes, removed)
+}
+
+/// deploy the files changed since the last deploy as a new release, and switch the site to it
+/// each step is retried, and fails with Reason::Deploy
+pub fn deploy() -> Result<(), err::Error> {
     info!("Deploying");
     let config = GlobalConfig::global();
-    let dst = config.scp_server.clone() + ":" + &config.scp_web_path;
-    match Command::new("scp")
-        .arg("-r")
-        .arg("public/")
-        .arg(&dst)
-        .output()
-    {
-        Err(e) => {
-            warn!("DEPLOY FAILED due to {:?}", e);
+    let conn = GlobalConnPool::global().0.get()?;
+    let target = current_target(&conn, &config)?;
+
+    let mut current = HashMap::new();
+    hash_files(&config.output_dir, &config.output_dir, &mut current)?;
+    let deployed = get_deployed_files(&conn, &target)?;
+
+    let mut summary = DeploySummary {
+        time: timestamp(),
+        method: config.deploy_config().method().to_string(),
+        live: true,
+        ..Default::default()
+    };
+    let (changes, removed) = diff(&current, &deployed, config.deploy_delete, &mut summary);
+
+    if changes.is_empty() {
+        info!("Nothing to Deploy");
+        return Ok(());
+    }
+
+    let release = release_name(summary.time, &get_releases(&conn, &target)?);
+    let deployer = deployer(&config);
+    retry("Deploying the release", || {
+        deployer.deploy_release(&config.output_dir, &changes, &release)
+    })?;
+    retry("Switching to the release", || {
+        deployer.switch_release(&release)
+    })?;
+
+    // the removed files are forgotten even if they are kept on the site
+    let tx = conn.unchecked_transaction()?;
+    for path in &changes.copied {
+        update_deployed_file(&tx, &target, path, &current[path])?;
+    }
+    for path in &removed {
+        remove_deployed_file(&tx, &target, path)?;
+    }
+    summary.release = Some(release.clone());
+    insert_deploy(&tx, &target, &summary)?;
+    insert_release_files(&tx, &target, &release, &current)?;
+    set_live_release(&tx, &target, &release)?;
+    tx.commit()?;
+    info!("Deployed Release {}, {}", release, summary);
+
+    prune_releases(deployer.as_ref(), &target)
+}
+
+/// switch the site back to an earlier release, the one before the live one if not given
+/// returns the release switched to
+pub fn rollback(release: Option<&str>) -> Result<String, err::Error> {
+    let config = GlobalConfig::global();
+    let conn = GlobalConnPool::global().0.get()?;
+    let target = current_target(&conn, &config)?;
+    let releases: Vec<DeploySummary> = get_releases(&conn, &target)?
+        .into_iter()
+        .filter(|r| !r.pruned)
+        .collect();
+
+    let chosen = match release {
+        Some(name) => releases.iter().find(|r| r.release.as_deref() == Some(name)),
+        None => match releases.iter().position(|r| r.live) {
+            Some(live) if live > 0 => releases.get(live - 1),
+            _ => None,
+        },
+    };
+    let release = match chosen {
+        Some(r) if r.live => {
+            return Err(err::Error::new(
+                err::Reason::Deploy,
+                format!("release {} is already live", r.release.clone().unwrap()),
+            ))
+        }
+        Some(r) => r.release.clone().unwrap(),
+        None => {
+            return Err(err::Error::new(
+                err::Reason::Deploy,
+                format!(
+                    "no release to roll back to among the {} ones kept on {}",
+                    releases.len(),
+                    target
+                ),
+            ))
         }
-        _ => {}
+    };
+
+    info!("Rolling Back to Release {}", release);
+    let deployer = deployer(&config);
+    retry("Switching to the release", || {
+        deployer.switch_release(&release)
+    })?;
+
+    // the next deploy starts from the files of the release
+    let tx = conn.unchecked_transaction()?;
+    set_live_release(&tx, &target, &release)?;
+    replace_deployed_files(&tx, &target, &get_release_files(&tx, &target, &release)?)?;
+    tx.commit()?;
+    info!("Rolled Back to Release {}", release);
+    Ok(release)
+}
+
+/// upload a picture to the target set in the config
+/// it's retried, and fails with Reason::Deploy
+pub fn upload_picture(picture: &Path) -> Result<(), err::Error> {
+    info!("Uploading Picture {:?}", picture);
+    let deployer = deployer(&GlobalConfig::global());
+    retry("Uploading the picture", || deployer.upload_picture(picture))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(path, hash)| (path.to_string(), hash.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_finds_added_changed_and_removed_files() {
+        let current = files(&[("a.html", "1"), ("b.html", "2"), ("c/d.html", "3")]);
+        let deployed = files(&[("a.html", "1"), ("b.html", "old"), ("gone.html", "4")]);
+
+        let mut summary = DeploySummary::default();
+        let (changes, removed) = diff(&current, &deployed, true, &mut summary);
+        assert_eq!(changes.copied, vec!["b.html", "c/d.html"]);
+        assert_eq!(changes.removed, vec!["gone.html"]);
+        assert_eq!(removed, vec!["gone.html"]);
+        assert_eq!(
+            (
+                summary.added,
+                summary.changed,
+                summary.removed,
+                summary.unchanged
+            ),
+            (1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn diff_keeps_removed_files_on_the_target_without_delete() {
+        let current = files(&[("a.html", "1")]);
+        let deployed = files(&[("a.html", "1"), ("gone.html", "2")]);
+
+        let mut summary = DeploySummary::default();
+        let (changes, removed) = diff(&current, &deployed, false, &mut summary);
+        assert!(changes.is_empty());
+        assert_eq!(removed, vec!["gone.html"]);
+        assert_eq!(summary.removed, 0);
+        assert_eq!(summary.unchanged, 1);
+    }
+
+    #[test]
+    fn release_names_never_collide() {
+        let release = |name: &str| DeploySummary {
+            release: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(release_name(100, &[]), "100");
+        assert_eq!(release_name(100, &[release("99")]), "100");
+        assert_eq!(release_name(100, &[release("100")]), "100-2");
+        assert_eq!(
+            release_name(100, &[release("100"), release("100-2")]),
+            "100-3"
+        );
     }
-    info!("Deployed");
 }
@@ -0,0 +1,179 @@
+use super::{copy_files, remove_files, run, Deployer};
+use crate::api::deploys::Changes;
+use crate::api::err;
+use log::info;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// the default clone is next to the output folder, e.g. public.deploy for public
+pub const WORK_DIR_SUFFIX: &str = ".deploy";
+
+/// the site and the pictures share the clone, so they are committed one at a time
+static WORK_DIR_LOCK: Mutex<()> = Mutex::new(());
+
+/// each release is tagged, e.g. release-1700000000
+const TAG_PREFIX: &str = "release-";
+
+/// committing into a clone of the remote and pushing it
+pub struct Git {
+    pub remote: String,
+    pub branch: String,
+    pub pic_path: String,
+    pub work_dir: PathBuf,
+}
+
+impl Git {
+    /// a git command running inside the clone
+    fn git(&self) -> Command {
+        let mut cmd = Command::new("git");
+        cmd.arg("-C").arg(&self.work_dir);
+        cmd
+    }
+
+    /// cloning the remote if necessary, and checking out the latest branch
+    fn checkout(&self) -> Result<(), err::Error> {
+        if !self.work_dir.join(".git").is_dir() {
+            info!("Cloning {} into {:?}", self.remote, self.work_dir);
+            run(Command::new("git")
+                .arg("clone")
+                .arg(&self.remote)
+                .arg(&self.work_dir))?;
+        }
+        run(self.git().arg("fetch").arg("origin"))?;
+
+        let remote_branch = String::from("origin/") + &self.branch;
+        let exists = self
+            .git()
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg("--quiet")
+            .arg(&remote_branch)
+            .output()?
+            .status
+            .success();
+        if exists {
+            run(self
+                .git()
+                .arg("checkout")
+                .arg("-f")
+                .arg("-B")
+                .arg(&self.branch)
+                .arg(&remote_branch))?;
+        } else {
+            // the first deploy of the branch
+            run(self
+                .git()
+                .arg("symbolic-ref")
+                .arg("HEAD")
+                .arg(String::from("refs/heads/") + &self.branch))?;
+        }
+        Ok(())
+    }
+
+    /// committing all the changes in the clone and pushing them, if there are any
+    fn commit_and_push(&self, message: &str) -> Result<(), err::Error> {
+        run(self.git().arg("add").arg("-A"))?;
+        let status = run(self.git().arg("status").arg("--porcelain"))?;
+        if status.stdout.is_empty() {
+            info!("Nothing to Commit");
+            return Ok(());
+        }
+
+        // a committer is needed even if git is not configured on this machine
+        let mut commit = self.git();
+        let configured = self
+            .git()
+            .arg("config")
+            .arg("user.email")
+            .output()?
+            .status
+            .success();
+        if !configured {
+            commit.args(["-c", "user.name=arce", "-c", "user.email=arce@localhost"]);
+        }
+        run(commit.arg("commit").arg("-m").arg(message))?;
+        run(self.git().arg("push").arg("origin").arg(&self.branch))?;
+        Ok(())
+    }
+
+    /// the pictures are kept out of the releases, as they are not deployed with the site
+    fn exclude_pictures(&self) -> String {
+        String::from(":(exclude)") + self.pic_path.trim_matches('/')
+    }
+}
+
+impl Deployer for Git {
+    /// the changed and removed files are committed together, and tagged
+    fn deploy_release(
+        &self,
+        output: &Path,
+        changes: &Changes,
+        release: &str,
+    ) -> Result<(), err::Error> {
+        let _lock = WORK_DIR_LOCK.lock().unwrap();
+        self.checkout()?;
+        copy_files(output, &changes.copied, &self.work_dir)?;
+        remove_files(&changes.removed, &self.work_dir)?;
+        self.commit_and_push(&format!("Deploy release {}", release))?;
+
+        let tag = String::from(TAG_PREFIX) + release;
+        run(self.git().arg("tag").arg("-f").arg(&tag))?;
+        run(self
+            .git()
+            .arg("push")
+            .arg("-f")
+            .arg("origin")
+            .arg(String::from("refs/tags/") + &tag))?;
+        Ok(())
+    }
+
+    /// the files of the release are restored in a new commit, nothing is committed if it's live
+    fn switch_release(&self, release: &str) -> Result<(), err::Error> {
+        let _lock = WORK_DIR_LOCK.lock().unwrap();
+        self.checkout()?;
+        let tag = String::from(TAG_PREFIX) + release;
+        run(self
+            .git()
+            .args(["rm", "-r", "-q", "--ignore-unmatch", "--", "."])
+            .arg(self.exclude_pictures()))?;
+        run(self
+            .git()
+            .arg("checkout")
+            .arg(&tag)
+            .args(["--", "."])
+            .arg(self.exclude_pictures()))?;
+        self.commit_and_push(&format!("Roll back to release {}", release))
+    }
+
+    /// only the tags are removed, the commits stay in the history
+    fn remove_releases(&self, releases: &[String]) -> Result<(), err::Error> {
+        let _lock = WORK_DIR_LOCK.lock().unwrap();
+        let refs: Vec<String> = releases
+            .iter()
+            .map(|r| String::from("refs/tags/") + TAG_PREFIX + r)
+            .collect();
+        run(self
+            .git()
+            .arg("push")
+            .arg("origin")
+            .arg("--delete")
+            .args(&refs))?;
+        let tags: Vec<String> = releases
+            .iter()
+            .map(|r| String::from(TAG_PREFIX) + r)
+            .collect();
+        run(self.git().arg("tag").arg("-d").args(&tags))?;
+        Ok(())
+    }
+
+    fn upload_picture(&self, picture: &Path) -> Result<(), err::Error> {
+        let _lock = WORK_DIR_LOCK.lock().unwrap();
+        self.checkout()?;
+        let name = picture.file_name().unwrap();
+        let dir = self.work_dir.join(self.pic_path.trim_matches('/'));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(picture, dir.join(name))?;
+        self.commit_and_push(&format!("Add the picture {}", name.to_string_lossy()))
+    }
+}
@@ -0,0 +1,37 @@
+use super::release::{prepare_local, remove_local, switch_local};
+use super::{copy_files, Deployer};
+use crate::api::deploys::Changes;
+use crate::api::err;
+use std::path::{Path, PathBuf};
+
+/// copying into local folders
+pub struct Local {
+    pub web_path: PathBuf,
+    pub pic_path: PathBuf,
+}
+
+impl Deployer for Local {
+    fn deploy_release(
+        &self,
+        output: &Path,
+        changes: &Changes,
+        release: &str,
+    ) -> Result<(), err::Error> {
+        let dir = prepare_local(&self.web_path, release, changes)?;
+        copy_files(output, &changes.copied, &dir)
+    }
+
+    fn switch_release(&self, release: &str) -> Result<(), err::Error> {
+        switch_local(&self.web_path, &self.pic_path, release)
+    }
+
+    fn remove_releases(&self, releases: &[String]) -> Result<(), err::Error> {
+        remove_local(&self.web_path, releases)
+    }
+
+    fn upload_picture(&self, picture: &Path) -> Result<(), err::Error> {
+        std::fs::create_dir_all(&self.pic_path)?;
+        std::fs::copy(picture, self.pic_path.join(picture.file_name().unwrap()))?;
+        Ok(())
+    }
+}
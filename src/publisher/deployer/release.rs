@@ -0,0 +1,228 @@
+/*
+ * Releases on the target, the site being a symlink to the live one
+ * e.g. /web/test -> /web/test.releases/1700000000
+ */
+use super::{remove_files, run, shell_quote};
+use crate::api::deploys::Changes;
+use crate::api::err;
+use crate::publisher::{link_dir, sibling};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// the releases are kept next to the site, e.g. /web/test.releases for /web/test
+pub const RELEASES_SUFFIX: &str = ".releases";
+
+/// the site deployed before releases is moved into this release when first switching
+pub const INITIAL_RELEASE: &str = "initial";
+
+/// the symlink is created under this name, and renamed over the site
+const LINK_SUFFIX: &str = ".link";
+
+/// the pictures are kept next to the site if pic_path is inside it, e.g. /web/test.pictures
+/// and linked into every release, so that no release misses the pictures uploaded after it
+const PICTURES_SUFFIX: &str = ".pictures";
+
+/// pic_path relative to the site, none if it's outside
+fn pictures_in_site(web_path: &Path, pic_path: &Path) -> Option<PathBuf> {
+    match pic_path.strip_prefix(web_path) {
+        Ok(rel) if !rel.as_os_str().is_empty() => Some(rel.to_path_buf()),
+        _ => None,
+    }
+}
+
+/// the folders of the changed files, which may not exist in the release yet
+fn folders(changes: &Changes) -> Vec<&str> {
+    let mut folders: Vec<&str> = changes
+        .copied
+        .iter()
+        .filter_map(|p| p.rsplit_once('/').map(|(f, _)| f))
+        .collect();
+    folders.sort();
+    folders.dedup();
+    folders
+}
+
+/// the folder of a release on a server
+pub fn remote_release(web_path: &str, release: &str) -> String {
+    web_path.trim_end_matches('/').to_string() + RELEASES_SUFFIX + "/" + release
+}
+
+/// running a script on a server with ssh
+fn ssh(server: &str, script: &str) -> Result<(), err::Error> {
+    run(Command::new("ssh").arg(server).arg(script))?;
+    Ok(())
+}
+
+/// creating a release on a server as a copy of the live site, ready for the changed files
+/// the files are hard linked, and the changed or removed ones are removed from the copy
+pub fn prepare_remote(
+    server: &str,
+    web_path: &str,
+    release: &str,
+    changes: &Changes,
+) -> Result<String, err::Error> {
+    let site = shell_quote(web_path.trim_end_matches('/'));
+    let dir = remote_release(web_path, release);
+    let quoted = shell_quote(&dir);
+
+    let mut script = format!(
+        "set -e; rm -rf {dir}; mkdir -p {dir}; if [ -d {site} ]; then cp -al {site}/. {dir}; fi; cd {dir}",
+        dir = quoted,
+        site = site
+    );
+    let stale: Vec<String> = changes
+        .copied
+        .iter()
+        .chain(&changes.removed)
+        .map(|p| shell_quote(p))
+        .collect();
+    if !stale.is_empty() {
+        script += &(String::from("; rm -f -- ") + &stale.join(" "));
+    }
+    let folders: Vec<String> = folders(changes).iter().map(|f| shell_quote(f)).collect();
+    if !folders.is_empty() {
+        script += &(String::from("; mkdir -p -- ") + &folders.join(" "));
+    }
+
+    ssh(server, &script)?;
+    Ok(dir)
+}
+
+/// pointing the site on a server to a release, by renaming a symlink over it
+/// the folder of pictures is linked into the release first, see PICTURES_SUFFIX
+pub fn switch_remote(
+    server: &str,
+    web_path: &str,
+    pic_path: &str,
+    release: &str,
+) -> Result<(), err::Error> {
+    let site = web_path.trim_end_matches('/');
+    let dir = remote_release(web_path, release);
+    let mut script = format!(
+        "set -e; test -d {dir}; if [ -d {site} ] && [ ! -L {site} ]; then mv {site} {initial}; fi",
+        dir = shell_quote(&dir),
+        site = shell_quote(site),
+        initial = shell_quote(&remote_release(web_path, INITIAL_RELEASE)),
+    );
+    if let Some(rel) = pictures_in_site(Path::new(web_path), Path::new(pic_path)) {
+        script += &format!(
+            "; p={pics}; mkdir -p {shared}; if [ ! -L \"$p\" ]; then if [ -d \"$p\" ]; then cp -alf \"$p\"/. {shared}/; rm -rf \"$p\"; fi; mkdir -p \"$(dirname \"$p\")\"; ln -s \"$(cd {shared} && pwd)\" \"$p\"; fi",
+            pics = shell_quote(&(dir.clone() + "/" + &rel.to_string_lossy())),
+            shared = shell_quote(&(site.to_string() + PICTURES_SUFFIX)),
+        );
+    }
+    script += &format!(
+        "; ln -sfn {dir} {link}; mv -Tf {link} {site}",
+        dir = shell_quote(&dir),
+        site = shell_quote(site),
+        link = shell_quote(&(site.to_string() + LINK_SUFFIX)),
+    );
+    ssh(server, &script)
+}
+
+/// removing releases from a server
+pub fn remove_remote(server: &str, web_path: &str, releases: &[String]) -> Result<(), err::Error> {
+    let dirs: Vec<String> = releases
+        .iter()
+        .map(|r| shell_quote(&remote_release(web_path, r)))
+        .collect();
+    ssh(server, &(String::from("rm -rf -- ") + &dirs.join(" ")))
+}
+
+/// the folder of a release in a local folder
+pub fn local_release(web_path: &Path, release: &str) -> PathBuf {
+    sibling(web_path, RELEASES_SUFFIX).join(release)
+}
+
+/// creating a release in a local folder as a copy of the live site, see prepare_remote
+pub fn prepare_local(
+    web_path: &Path,
+    release: &str,
+    changes: &Changes,
+) -> Result<PathBuf, err::Error> {
+    let dir = local_release(web_path, release);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    if web_path.is_dir() {
+        link_dir(web_path, &dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    remove_files(&changes.copied, &dir)?;
+    remove_files(&changes.removed, &dir)?;
+    Ok(dir)
+}
+
+/// creating a symlink to a folder, which releases in local folders depend on
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> Result<(), err::Error> {
+    std::os::unix::fs::symlink(std::path::absolute(target)?, link)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &Path, link: &Path) -> Result<(), err::Error> {
+    Err(err::Error::new(
+        err::Reason::Deploy,
+        format!(
+            "{:?} not linked, releases in local folders are only supported on unix",
+            link
+        ),
+    ))
+}
+
+/// linking the folder of pictures into a local release, see PICTURES_SUFFIX
+/// the pictures already in the release are moved out first
+fn link_pictures(web_path: &Path, pic_path: &Path, dir: &Path) -> Result<(), err::Error> {
+    let pics = match pictures_in_site(web_path, pic_path) {
+        Some(rel) => dir.join(rel),
+        None => return Ok(()),
+    };
+    let shared = sibling(web_path, PICTURES_SUFFIX);
+    std::fs::create_dir_all(&shared)?;
+    if pics.is_symlink() {
+        return Ok(());
+    }
+    if pics.is_dir() {
+        link_dir(&pics, &shared)?;
+        std::fs::remove_dir_all(&pics)?;
+    }
+    std::fs::create_dir_all(pics.parent().unwrap())?;
+    symlink(&shared, &pics)
+}
+
+/// pointing a local site to a release, see switch_remote
+pub fn switch_local(web_path: &Path, pic_path: &Path, release: &str) -> Result<(), err::Error> {
+    let dir = local_release(web_path, release);
+    if !dir.is_dir() {
+        return Err(err::Error::new(
+            err::Reason::Deploy,
+            format!("release {:?} not found", dir),
+        ));
+    }
+    if web_path.is_dir() && !web_path.is_symlink() {
+        std::fs::rename(web_path, local_release(web_path, INITIAL_RELEASE))?;
+    }
+    link_pictures(web_path, pic_path, &dir)?;
+
+    let link = sibling(web_path, LINK_SUFFIX);
+    match std::fs::remove_file(&link) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    symlink(&dir, &link)?;
+    std::fs::rename(&link, web_path)?;
+    Ok(())
+}
+
+/// removing releases from a local folder
+pub fn remove_local(web_path: &Path, releases: &[String]) -> Result<(), err::Error> {
+    for release in releases {
+        match std::fs::remove_dir_all(local_release(web_path, release)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
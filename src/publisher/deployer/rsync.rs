@@ -0,0 +1,84 @@
+use super::release::{
+    prepare_local, prepare_remote, remove_local, remove_remote, switch_local, switch_remote,
+};
+use super::{run, Deployer};
+use crate::api::deploys::Changes;
+use crate::api::err;
+use std::path::Path;
+use std::process::Command;
+
+/// copying the changed files with rsync, into a local folder if server is not set
+pub struct Rsync {
+    pub server: Option<String>,
+    pub web_path: String,
+    pub pic_path: String,
+}
+
+impl Rsync {
+    /// the destination of rsync, a folder ending with /
+    fn dst(&self, path: &str) -> String {
+        let path = path.trim_end_matches('/').to_string() + "/";
+        match self.server {
+            Some(ref server) => server.clone() + ":" + &path,
+            None => path,
+        }
+    }
+}
+
+impl Deployer for Rsync {
+    /// the changed files are listed in a file read by rsync, which creates the folders
+    fn deploy_release(
+        &self,
+        output: &Path,
+        changes: &Changes,
+        release: &str,
+    ) -> Result<(), err::Error> {
+        let dir = match self.server {
+            Some(ref server) => prepare_remote(server, &self.web_path, release, changes)?,
+            None => prepare_local(Path::new(&self.web_path), release, changes)?
+                .to_string_lossy()
+                .to_string(),
+        };
+        if changes.copied.is_empty() {
+            return Ok(());
+        }
+
+        let list = std::env::temp_dir().join(format!("arce-rsync-{}", std::process::id()));
+        std::fs::write(&list, changes.copied.join("\n") + "\n")?;
+        let src = output.to_string_lossy().trim_end_matches('/').to_string() + "/";
+        let result = run(Command::new("rsync")
+            .arg("-a")
+            .arg(String::from("--files-from=") + &list.to_string_lossy())
+            .arg(src)
+            .arg(self.dst(&dir)));
+        std::fs::remove_file(&list)?;
+        result?;
+        Ok(())
+    }
+
+    fn switch_release(&self, release: &str) -> Result<(), err::Error> {
+        match self.server {
+            Some(ref server) => switch_remote(server, &self.web_path, &self.pic_path, release),
+            None => switch_local(
+                Path::new(&self.web_path),
+                Path::new(&self.pic_path),
+                release,
+            ),
+        }
+    }
+
+    fn remove_releases(&self, releases: &[String]) -> Result<(), err::Error> {
+        match self.server {
+            Some(ref server) => remove_remote(server, &self.web_path, releases),
+            None => remove_local(Path::new(&self.web_path), releases),
+        }
+    }
+
+    fn upload_picture(&self, picture: &Path) -> Result<(), err::Error> {
+        run(Command::new("rsync")
+            .arg("-a")
+            .arg(picture)
+            .arg(self.dst(&self.pic_path)))?;
+        Ok(())
+    }
+}
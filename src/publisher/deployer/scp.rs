@@ -0,0 +1,55 @@
+use super::release::{prepare_remote, remove_remote, switch_remote};
+use super::{run, Deployer};
+use crate::api::deploys::Changes;
+use crate::api::err;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// copying the changed files with scp, releases are managed with ssh
+pub struct Scp {
+    pub server: String,
+    pub web_path: String,
+    pub pic_path: String,
+}
+
+impl Deployer for Scp {
+    /// the files are copied folder by folder into the release
+    fn deploy_release(
+        &self,
+        output: &Path,
+        changes: &Changes,
+        release: &str,
+    ) -> Result<(), err::Error> {
+        let dir = prepare_remote(&self.server, &self.web_path, release, changes)?;
+
+        let mut folders: BTreeMap<&str, Vec<PathBuf>> = BTreeMap::new();
+        for path in &changes.copied {
+            let folder = path.rsplit_once('/').map(|(f, _)| f).unwrap_or("");
+            folders.entry(folder).or_default().push(output.join(path));
+        }
+        for (folder, files) in &folders {
+            let dst = self.server.clone() + ":" + &dir + "/" + folder;
+            run(Command::new("scp").args(files).arg(&dst))?;
+        }
+        Ok(())
+    }
+
+    fn switch_release(&self, release: &str) -> Result<(), err::Error> {
+        switch_remote(&self.server, &self.web_path, &self.pic_path, release)
+    }
+
+    fn remove_releases(&self, releases: &[String]) -> Result<(), err::Error> {
+        remove_remote(&self.server, &self.web_path, releases)
+    }
+
+    fn upload_picture(&self, picture: &Path) -> Result<(), err::Error> {
+        let dst = self.server.clone()
+            + ":"
+            + &self.pic_path
+            + "/"
+            + picture.file_name().unwrap().to_str().unwrap();
+        run(Command::new("scp").arg(picture).arg(&dst))?;
+        Ok(())
+    }
+}
@@ -11,8 +11,8 @@ const MAX_CONTENT: usize = 262144;
 // Convert u8s to hexs
 fn array2string(enc: &[u8]) -> Result<String, err::Error> {
     let mut s = String::new();
-    for i in 0..enc.len() {
-        s += &format!("{:x}{:x}", enc[i] / 16, enc[i] % 16)
+    for b in enc {
+        s += &format!("{:x}{:x}", b / 16, b % 16)
     };
     Ok(s)
 }
@@ -25,7 +25,7 @@ pub fn encrypt(content: String, password: String) -> Result<String, err::Error>
 
     // Right now the same iv is used for every article
     // A better way is to generate per article
-    if let None = config.iv {
+    if config.iv.is_none() {
         warn!("IV not set. Cannot Encrypt Articles.");
         return Err(err::Error{
             reason: err::Reason::Internal,
@@ -33,14 +33,14 @@ pub fn encrypt(content: String, password: String) -> Result<String, err::Error>
         })
     }
     let mut iv = [0x0; 16];
-    iv.copy_from_slice(&config.iv.clone().unwrap().as_bytes());
+    iv.copy_from_slice(config.iv.clone().unwrap().as_bytes());
 
     // Generate the key from Password with some simple method
     // Maybe i should switch to PBKDF2 in the future
     let mut key = [0x42; 16];
     let mut loc = 0;
-    for i in 0..16 {
-        key[i] = password.bytes().nth(loc).unwrap();
+    for k in key.iter_mut() {
+        *k = password.as_bytes()[loc];
         loc = ( loc + 1 ) % password.len();
     }
 
@@ -53,5 +53,5 @@ pub fn encrypt(content: String, password: String) -> Result<String, err::Error>
         .unwrap();
 
     info!("Encrypted");
-    Ok(array2string(encrypted)?)
+    array2string(encrypted)
 }
\ No newline at end of file
@@ -0,0 +1,198 @@
+use log::{debug, info};
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::api::err;
+use crate::model::outputs::{get_outputs, remove_output, update_output};
+
+/// the files generated in a render, compared with the ones of the last render
+/// paths are relative to the output folder
+/// files are written into root, the folder being rendered into
+/// the renders of the preview server are recorded apart from the builds
+#[derive(Default)]
+pub struct Manifest {
+    root: PathBuf,
+    preview: bool,
+    previous: HashMap<String, String>,
+    generated: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// starting a render into root, with the files generated last time
+    pub fn load(conn: &Connection, root: &Path, preview: bool) -> Result<Self, err::Error> {
+        Ok(Manifest {
+            root: root.to_path_buf(),
+            preview,
+            previous: get_outputs(conn, preview)?,
+            generated: HashMap::new(),
+        })
+    }
+
+    /// writing a generated file, unless it's unchanged since the last render
+    /// returns whether it's written
+    pub fn write(&mut self, path: &str, content: &[u8]) -> Result<bool, err::Error> {
+        let path = path.trim_start_matches('/');
+        let hash = sha256::digest(content);
+        let dst = self.root.join(path);
+
+        let unchanged = self.previous.get(path) == Some(&hash) && dst.is_file();
+        self.generated.insert(path.to_string(), hash);
+        if unchanged {
+            debug!("{:?} unchanged", dst);
+            return Ok(false);
+        }
+
+        // replaced instead of written into, as it may be linked to the live output
+        if dst.is_file() {
+            std::fs::remove_file(&dst)?;
+        }
+        std::fs::create_dir_all(dst.parent().unwrap())?;
+        std::fs::write(&dst, content)?;
+        info!("{:?} rendered", dst);
+        Ok(true)
+    }
+
+    /// removing the files no longer generated
+    pub fn prune(&self) -> Result<(), err::Error> {
+        for path in self.previous.keys() {
+            if self.generated.contains_key(path) {
+                continue;
+            }
+            info!("Removing Stale File {:?}", path);
+            match std::fs::remove_file(self.root.join(path)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// saving the generated files, once they are in place
+    pub fn save(self, conn: &Connection) -> Result<(), err::Error> {
+        let tx = conn.unchecked_transaction()?;
+        for path in self.previous.keys() {
+            if !self.generated.contains_key(path) {
+                remove_output(&tx, self.preview, path)?;
+            }
+        }
+        for (path, hash) in &self.generated {
+            if self.previous.get(path) != Some(hash) {
+                update_output(&tx, self.preview, path, hash)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::migrations::migrate;
+
+    /// an empty folder to render into, removed once dropped
+    struct Root(PathBuf);
+
+    impl Root {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("arce-manifest-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Root(dir)
+        }
+    }
+
+    impl Drop for Root {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn writes_only_changed_files() {
+        let root = Root::new("write");
+        let conn = database();
+
+        let mut manifest = Manifest::load(&conn, &root.0, false).unwrap();
+        assert!(manifest.write("/index/1.html", b"one").unwrap());
+        assert!(manifest.write("about.html", b"about").unwrap());
+        manifest.save(&conn).unwrap();
+        assert_eq!(std::fs::read(root.0.join("index/1.html")).unwrap(), b"one");
+
+        let mut manifest = Manifest::load(&conn, &root.0, false).unwrap();
+        assert!(!manifest.write("/index/1.html", b"one").unwrap());
+        assert!(manifest.write("about.html", b"changed").unwrap());
+        assert_eq!(
+            std::fs::read(root.0.join("about.html")).unwrap(),
+            b"changed"
+        );
+
+        // a file missing from the folder is written again, even if unchanged
+        std::fs::remove_file(root.0.join("index/1.html")).unwrap();
+        assert!(manifest.write("/index/1.html", b"one").unwrap());
+        assert!(root.0.join("index/1.html").is_file());
+    }
+
+    #[test]
+    fn prunes_files_no_longer_generated() {
+        let root = Root::new("prune");
+        let conn = database();
+
+        let mut manifest = Manifest::load(&conn, &root.0, false).unwrap();
+        manifest.write("kept.html", b"kept").unwrap();
+        manifest.write("stale.html", b"stale").unwrap();
+        manifest.save(&conn).unwrap();
+
+        let mut manifest = Manifest::load(&conn, &root.0, false).unwrap();
+        manifest.write("kept.html", b"kept").unwrap();
+        manifest.prune().unwrap();
+        assert!(root.0.join("kept.html").is_file());
+        assert!(!root.0.join("stale.html").exists());
+        manifest.save(&conn).unwrap();
+
+        let outputs = get_outputs(&conn, false).unwrap();
+        assert!(outputs.contains_key("kept.html"));
+        assert!(!outputs.contains_key("stale.html"));
+
+        // a stale file already gone is not an error
+        let manifest = Manifest {
+            root: root.0.clone(),
+            preview: false,
+            previous: HashMap::from([(String::from("gone.html"), String::new())]),
+            generated: HashMap::new(),
+        };
+        manifest.prune().unwrap();
+    }
+
+    #[test]
+    fn keeps_previews_apart_from_builds() {
+        let build = Root::new("build");
+        let preview = Root::new("preview");
+        let conn = database();
+
+        let mut manifest = Manifest::load(&conn, &build.0, false).unwrap();
+        manifest.write("index.html", b"built").unwrap();
+        manifest.save(&conn).unwrap();
+
+        // a preview neither sees nor prunes the files of the build
+        let mut manifest = Manifest::load(&conn, &preview.0, true).unwrap();
+        assert!(manifest.write("index.html", b"built").unwrap());
+        manifest.write("draft.html", b"draft").unwrap();
+        manifest.prune().unwrap();
+        manifest.save(&conn).unwrap();
+
+        let outputs = get_outputs(&conn, false).unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert!(outputs.contains_key("index.html"));
+        assert_eq!(get_outputs(&conn, true).unwrap().len(), 2);
+    }
+}
@@ -1,25 +1,40 @@
-use log::{info, warn};
+use log::{debug, info, warn};
 use pulldown_cmark::{html, Options, Parser};
 use regex::Regex;
 use slug::slugify;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use crate::{
     api::{
-        articles::{Article, ArticleYaml},
+        articles::{Article, ArticleInfo, ArticlePicture, ArticleYaml, CachedArticle},
         config::GlobalConfig,
         err,
         pictures::Picture,
         sync::GlobalConnPool,
     },
-    model::articles::get_articles,
+    model::{
+        article_cache::{get_cached_article, prune_article_cache, update_cached_article},
+        articles::get_articles,
+    },
 };
 
+/// the last modification time of a file, in millis
+fn modified(p: &Path) -> Result<u64, err::Error> {
+    let time = std::fs::metadata(p)?.modified()?;
+    Ok(time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64)
+}
+
 /// replace the local pictures in the md with online pic urls
-fn picture_replace(mut content: String) -> Result<String, err::Error> {
+/// returns the pictures replaced
+fn picture_replace(content: &mut String) -> Result<Vec<ArticlePicture>, err::Error> {
     let re = Regex::new(r"!\[([^\]]+)\]\(([^\)]+)\)").unwrap();
 
     let config = GlobalConfig::global();
+    let mut pictures = Vec::new();
 
     for cap in re.captures_iter(&content.clone()) {
         if !cap[2].starts_with(&config.pic_replace_prefix) {
@@ -27,16 +42,23 @@ fn picture_replace(mut content: String) -> Result<String, err::Error> {
         };
 
         // register and upload the picture
-        let p = Picture::from_dir(PathBuf::from(&cap[2]))?;
+        let local = PathBuf::from(&cap[2]);
+        let modified = modified(&local)?;
+        let p = Picture::from_dir(local.clone())?;
         let path = p.register()?;
         let to =
-            config.pic_cloud_prefix.clone() + "/" + &path.file_name().unwrap().to_str().unwrap();
+            config.pic_cloud_prefix.clone() + "/" + path.file_name().unwrap().to_str().unwrap();
 
         // replace the urls
-        content = content.replace(&cap[2], &to);
+        *content = content.replace(&cap[2], &to);
+        pictures.push(ArticlePicture {
+            path: local,
+            modified,
+            url: to,
+        });
     }
 
-    Ok(content)
+    Ok(pictures)
 }
 
 /// getting the yaml front matter
@@ -65,12 +87,14 @@ fn read_article_header(content: String) -> Result<Article, err::Error> {
         date: yaml.date,
         summary: yaml.summary.unwrap_or_default(),
         url: yaml.path.unwrap_or_else(|| slugify(&yaml.title)),
-        category: yaml.category.unwrap_or(String::from("未分类")),
+        category: yaml
+            .category
+            .unwrap_or_else(|| GlobalConfig::global().t("category_default")),
         headline: yaml.headline.unwrap_or(false),
         content: body,
         encrypt: yaml.password.is_some(),
-        password: yaml.password.unwrap_or(String::new()),
-        hint: yaml.hint.unwrap_or(String::new())
+        password: yaml.password.unwrap_or_default(),
+        hint: yaml.hint.unwrap_or_default()
     })
 }
 
@@ -88,29 +112,85 @@ fn markdown_paser(mut a: Article) -> Result<Article, err::Error> {
     Ok(a)
 }
 
+/// the hash of everything the processed article depends on, besides the pictures
+fn source_hash(content: &str, a: &ArticleInfo, config: &GlobalConfig) -> String {
+    sha256::digest(
+        [
+            content,
+            &a.deploy_folder,
+            &config.pic_replace_prefix,
+            &config.pic_cloud_prefix,
+            &config.t("category_default"),
+        ]
+        .join("\n"),
+    )
+}
+
+/// whether the cached article is still valid, i.e. no picture is modified since
+fn is_fresh(cached: &CachedArticle, hash: &str) -> bool {
+    cached.hash == hash
+        && cached
+            .pictures
+            .iter()
+            .all(|p| modified(&p.path).ok() == Some(p.modified))
+}
+
+/// read and render a single article, unless it's unchanged since cached
+fn process_article(a: ArticleInfo) -> Result<Article, err::Error> {
+    let conn = GlobalConnPool::global().0.get()?;
+    let config = GlobalConfig::global();
+    let content = std::fs::read_to_string(&a.path)?;
+    let hash = source_hash(&content, &a, &config);
+    if let Some(mut cached) = get_cached_article(&conn, &a.path, config.preview)? {
+        if is_fresh(&cached, &hash) {
+            debug!("Article {:?} Unchanged", &a.path);
+            // the password is never cached
+            if cached.article.encrypt {
+                cached.article.password = read_article_header(content)?.password;
+            }
+            return Ok(cached.article);
+        }
+    }
+
+    info!("Handling Article {:?}", &a.path);
+    let mut article = read_article_header(content)?;
+    let pictures = picture_replace(&mut article.content)?;
+    article = markdown_paser(article)?;
+    article.url = String::from("/") + &a.deploy_folder + "/" + &article.url + ".html";
+
+    update_cached_article(
+        &conn,
+        &CachedArticle {
+            path: a.path,
+            hash,
+            article: article.clone(),
+            pictures,
+        },
+        config.preview,
+    )?;
+    Ok(article)
+}
+
 /// render the article bodies, and do the necessary processes
-pub fn process_articles() -> Result<Vec<Article>, err::Error> {
+/// articles failing to render are skipped, unless strict is set
+pub fn process_articles(strict: bool) -> Result<Vec<Article>, err::Error> {
     let conn = GlobalConnPool::global().0.get().unwrap();
-    let mut articles = get_articles(&conn)?
-        .into_iter()
-        .map(|a| -> Result<Article, err::Error> {
-            info!("Handling Article {:?}", &a.path);
-            let content = std::fs::read_to_string(a.path)?;
-            let mut article = read_article_header(content)?;
-            article.content = picture_replace(article.content)?;
-            article = markdown_paser(article)?;
-            article.url = String::from("/") + &a.deploy_folder + "/" + &article.url + ".html";
-            Ok(article)
-        })
-        .filter_map(|result| {
-            if let Err(e) = result {
-                warn!("Error Occured: {}", e);
-                return None;
-            };
-            return Some(result.unwrap());
-        })
-        .collect::<Vec<Article>>();
-    articles.sort_by(|a, b| (&b.date).cmp(&a.date));
+    let mut articles = Vec::new();
+    for a in get_articles(&conn)? {
+        let path = a.path.clone();
+        match process_article(a) {
+            Ok(article) => articles.push(article),
+            Err(e) if strict => {
+                return Err(err::Error::new(
+                    e.reason,
+                    format!("{:?}: {}", path, e.message),
+                ))
+            }
+            Err(e) => warn!("Error Occured: {}", e),
+        }
+    }
+    prune_article_cache(&conn)?;
+    articles.sort_by(|a, b| b.date.cmp(&a.date));
     info!("Handled {} articles in total",articles.len());
     Ok(articles)
 }
@@ -4,38 +4,52 @@ use serde::Serialize;
 use sitemap::structs::UrlEntry;
 use sitemap::{structs::UrlEntryBuilder, writer::SiteMapWriter};
 use slug::slugify;
-use std::{cell::RefCell, cmp::min, io::Write, path::PathBuf};
+use std::{cell::RefCell, cmp::min, collections::HashMap, path::Path, sync::Arc};
 use tera::{Context, Tera};
 
+use crate::api::sync::GlobalConnPool;
 use crate::api::{articles::Article, config::GlobalConfig, err, pictures::PhotographyPictureBrief};
 use crate::publisher::encrypter;
+use crate::publisher::manifest::Manifest;
 
 /// Page Info context for Tera
 #[derive(Serialize, Clone)]
 struct Page {
     pub title: String,
-    pub tcolor: Vec<String>,
 }
 
 impl Page {
-    fn new(i: usize, s: String) -> Self {
-        let v = [0, 1, 2, 3]
-            .into_iter()
-            .map(|x| {
-                if i == x {
-                    "black".to_string()
-                } else {
-                    "grey".to_string()
-                }
-            })
-            .collect();
-        Page {
-            title: s,
-            tcolor: v,
-        }
+    fn new(s: String) -> Self {
+        Page { title: s }
     }
 }
 
+/// navigation menu entry context for tera
+#[derive(Serialize)]
+struct NavItem {
+    pub label: String,
+    pub url: String,
+    pub active: bool,
+}
+
+/// the navigation menu of a page, path is relative to the output folder
+fn nav(config: &GlobalConfig, path: &str) -> Vec<NavItem> {
+    let path = "/".to_string() + path.trim_start_matches('/');
+    config
+        .nav
+        .iter()
+        .map(|entry| NavItem {
+            label: config.t(&entry.label),
+            url: entry.url.clone(),
+            active: if entry.active.is_empty() {
+                path == entry.url
+            } else {
+                entry.active.iter().any(|a| path.starts_with(a.as_str()))
+            },
+        })
+        .collect()
+}
+
 /// Navigator info context for tera
 #[derive(Serialize, Clone)]
 struct Navigator {
@@ -76,36 +90,65 @@ impl Category {
 struct Encrypt {
     pub hint: String,
     pub content: String,
-    pub iv: String
+    pub iv: String,
 }
 
 lazy_static! {
-    pub static ref TERA: Tera = Tera::new("template/**/*.html").unwrap();
+    pub static ref TERA: Tera = {
+        let glob = GlobalConfig::global().template_dir.join("**/*.html");
+        let mut tera = Tera::new(glob.to_str().unwrap()).unwrap();
+        tera.register_function("t", translate);
+        tera
+    };
 }
 
-thread_local! {static URL_ENTRY: RefCell<Vec<UrlEntryBuilder>> = RefCell::new(vec![])}
-
-/// generate html(s)
-fn gen_html(context: &Context, template: &str, dst: &str) -> Result<(), err::Error> {
-    let path = PathBuf::from(dst);
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix)?;
+/// the ui strings in templates, e.g. {{ t(key="nav_home") }}
+/// translated with the config the render started with, so all pages share one locale
+fn translate(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let key = match args.get("key").and_then(|k| k.as_str()) {
+        Some(key) => key,
+        None => return Err("t() takes a string argument key".into()),
+    };
+    let config = RENDER_CONFIG
+        .with(|c| c.borrow().clone())
+        .unwrap_or_else(GlobalConfig::global);
+    Ok(tera::Value::String(config.t(key)))
+}
 
-    let t = TERA.render(template, &context).unwrap();
-    let mut f = std::fs::File::create(&dst)?;
-    f.write_all(t.as_bytes())?;
+thread_local! {static URL_ENTRY: RefCell<Vec<UrlEntryBuilder>> = const { RefCell::new(vec![]) }}
+thread_local! {static MANIFEST: RefCell<Manifest> = RefCell::new(Manifest::default())}
+thread_local! {static RENDER_CONFIG: RefCell<Option<Arc<GlobalConfig>>> = const { RefCell::new(None) }}
 
-    info!("{} rendered", dst);
+/// writing a file into the folder being rendered into, unless it's unchanged
+/// dst is relative to the output folder
+fn write_output(dst: &str, content: &[u8]) -> Result<(), err::Error> {
+    MANIFEST.with(|m| m.borrow_mut().write(dst, content))?;
     Ok(())
 }
 
+/// generate html(s)
+/// dst is relative to the output folder
+/// the navigation menu is added to the context
+fn gen_html(
+    config: &GlobalConfig,
+    context: &mut Context,
+    template: &str,
+    dst: &str,
+) -> Result<(), err::Error> {
+    context.insert("nav", &nav(config, dst));
+    let t = TERA.render(template, context)?;
+    write_output(dst, t.as_bytes())
+}
+
 /// generate index page
-fn index(articles: Vec<Article>) -> Result<(), err::Error> {
+fn index(config: &GlobalConfig, articles: Vec<Article>) -> Result<(), err::Error> {
     let mut context = Context::new();
-    let config = GlobalConfig::global();
     context.insert("global", config);
-    context.insert("need_nav", &true);
-    context.insert("page", &Page::new(0, "文章 | ".to_string() + &config.title));
+    context.insert("need_pager", &true);
+    context.insert(
+        "page",
+        &Page::new(config.t("title_articles") + " | " + &config.title),
+    );
 
     let headlines: Vec<Article> = articles.into_iter().filter(|a| a.headline).collect();
     let page = (headlines.len()) / 20 + 1;
@@ -120,19 +163,19 @@ fn index(articles: Vec<Article>) -> Result<(), err::Error> {
             "article_briefs",
             &headlines[(i - 1) * 20..min((i) * 20, headlines.len())],
         );
-        context.insert("nav", &Navigator::new(page, i));
+        context.insert("pager", &Navigator::new(page, i));
 
         gen_html(
-            &context,
+            config,
+            &mut context,
             "category.html",
-            &("public/index/".to_string() + &i.to_string() + ".html"),
+            &("index/".to_string() + &i.to_string() + ".html"),
         )?;
 
         URL_ENTRY.with(|v| {
             // Assuming index is set to /index/1.html
             (*v.borrow_mut()).push(
-                UrlEntry::builder()
-                    .loc(config.url.clone() + &"/index/".to_string() + &i.to_string() + ".html"),
+                UrlEntry::builder().loc(config.url.clone() + "/index/" + &i.to_string() + ".html"),
             );
         });
     }
@@ -140,31 +183,33 @@ fn index(articles: Vec<Article>) -> Result<(), err::Error> {
 }
 
 /// generate article (detail) page
-fn article(articles: Vec<Article>, c: Category) -> Result<(), err::Error> {
+fn article(config: &GlobalConfig, articles: Vec<Article>, c: Category) -> Result<(), err::Error> {
     let mut context = Context::new();
-    let config = GlobalConfig::global();
     context.insert("global", config);
-    context.insert("need_nav", &false);
+    context.insert("need_pager", &false);
     context.insert("category", &c);
 
     for a in articles {
         context.insert(
             "page",
-            &Page::new(4, a.title.to_string() + " | " + &config.title),
+            &Page::new(a.title.to_string() + " | " + &config.title),
         );
         context.insert("article", &a);
 
         if a.encrypt {
-            context.insert("encrypt", &Encrypt{
-                content: encrypter::encrypt(a.content, a.password)?,
-                hint: a.hint,
-                iv: config.iv.clone().unwrap()
-            });
-            gen_html(&context, "article-enc.html", &("public".to_string() + &a.url))?;
+            context.insert(
+                "encrypt",
+                &Encrypt {
+                    content: encrypter::encrypt(a.content, a.password)?,
+                    hint: a.hint,
+                    iv: config.iv.clone().unwrap(),
+                },
+            );
+            gen_html(config, &mut context, "article-enc.html", &a.url)?;
         } else {
-            gen_html(&context, "article.html", &("public".to_string() + &a.url))?;
+            gen_html(config, &mut context, "article.html", &a.url)?;
         }
-        
+
         URL_ENTRY.with(|v| {
             (*v.borrow_mut()).push(
                 UrlEntry::builder().loc(config.url.clone() + "/" + a.url.clone().trim_matches('/')),
@@ -175,34 +220,38 @@ fn article(articles: Vec<Article>, c: Category) -> Result<(), err::Error> {
 }
 
 /// generate category (detail) page, also the articles
-fn category(c: Category, a: Vec<Article>) -> Result<(), err::Error> {
+fn category(config: &GlobalConfig, c: Category, a: Vec<Article>) -> Result<(), err::Error> {
     let mut context = Context::new();
-    let config = GlobalConfig::global();
     context.insert("global", config);
-    context.insert("need_nav", &false);
-    context.insert("page", &Page::new(2, "分类 | ".to_string() + &config.title));
+    context.insert("need_pager", &false);
+    context.insert(
+        "page",
+        &Page::new(config.t("title_categories") + " | " + &config.title),
+    );
 
     let articles: Vec<Article> = a.into_iter().filter(|a| a.category == c.title).collect();
 
     context.insert("article_briefs", &articles);
-    gen_html(&context, "category.html", &("public/".to_string() + &c.url))?;
+    gen_html(config, &mut context, "category.html", &c.url)?;
 
     URL_ENTRY.with(|v| {
         (*v.borrow_mut()).push(UrlEntry::builder().loc(config.url.clone() + "/" + &c.url));
     });
 
-    article(articles, c)?;
+    article(config, articles, c)?;
 
     Ok(())
 }
 
 /// generate pages for category lists, also the categories and articles
-fn article_category(articles: Vec<Article>) -> Result<(), err::Error> {
+fn article_category(config: &GlobalConfig, articles: Vec<Article>) -> Result<(), err::Error> {
     let mut context = Context::new();
-    let config = GlobalConfig::global();
     context.insert("global", config);
-    context.insert("need_nav", &false);
-    context.insert("page", &Page::new(2, "分类 | ".to_string() + &config.title));
+    context.insert("need_pager", &false);
+    context.insert(
+        "page",
+        &Page::new(config.t("title_categories") + " | " + &config.title),
+    );
 
     let mut categories = articles
         .clone()
@@ -212,13 +261,14 @@ fn article_category(articles: Vec<Article>) -> Result<(), err::Error> {
     categories.sort();
     categories.dedup();
     debug!("Categories {:?}", categories);
-    let categories: Vec<Category> = categories.into_iter().map(|c| Category::new(c)).collect();
+    let categories: Vec<Category> = categories.into_iter().map(Category::new).collect();
     context.insert("categories", &categories);
 
     gen_html(
-        &context,
+        config,
+        &mut context,
         "category-list.html",
-        "public/article_category.html",
+        "article_category.html",
     )?;
 
     URL_ENTRY.with(|v| {
@@ -227,39 +277,45 @@ fn article_category(articles: Vec<Article>) -> Result<(), err::Error> {
     });
 
     for c in categories {
-        category(c, articles.clone())?;
+        category(config, c, articles.clone())?;
     }
 
     Ok(())
 }
 
 /// generate the gallery pages
-fn gallery(mut pictures: Vec<PhotographyPictureBrief>) -> Result<(), err::Error> {
+fn gallery(
+    config: &GlobalConfig,
+    mut pictures: Vec<PhotographyPictureBrief>,
+) -> Result<(), err::Error> {
     let mut context = Context::new();
-    let config = GlobalConfig::global();
     context.insert("global", config);
-    context.insert("need_nav", &true);
-    context.insert("page", &Page::new(1, "照片 | ".to_string() + &config.title));
-    pictures = pictures.into_iter().filter(|p| p.selected).collect();
+    context.insert("need_pager", &true);
+    context.insert(
+        "page",
+        &Page::new(config.t("title_photos") + " | " + &config.title),
+    );
+    pictures.retain(|p| p.selected);
 
-    let page = (pictures.len()-1) / 20 + 1;
+    let page = pictures.len().saturating_sub(1) / 20 + 1;
 
     for i in 1..=page {
         context.insert(
             "pics",
             &pictures[(i - 1) * 20..min((i) * 20, pictures.len())],
         );
-        context.insert("nav", &Navigator::new(page, i));
+        context.insert("pager", &Navigator::new(page, i));
         gen_html(
-            &context,
+            config,
+            &mut context,
             "picture.html",
-            &("public/gallery/".to_string() + &i.to_string() + ".html"),
+            &("gallery/".to_string() + &i.to_string() + ".html"),
         )?;
 
         URL_ENTRY.with(|v| {
             (*v.borrow_mut()).push(
                 UrlEntry::builder()
-                    .loc(config.url.clone() + &"/gallery/".to_string() + &i.to_string() + ".html"),
+                    .loc(config.url.clone() + "/gallery/" + &i.to_string() + ".html"),
             );
         });
     }
@@ -268,32 +324,38 @@ fn gallery(mut pictures: Vec<PhotographyPictureBrief>) -> Result<(), err::Error>
 }
 
 /// generate the picture pages
-fn picture(pictures: Vec<PhotographyPictureBrief>) -> Result<(), err::Error> {
+fn picture(
+    config: &GlobalConfig,
+    pictures: Vec<PhotographyPictureBrief>,
+) -> Result<(), err::Error> {
     let mut context = Context::new();
-    let config = GlobalConfig::global();
     context.insert("global", config);
-    context.insert("need_nav", &true);
-    context.insert("page", &Page::new(3, "图库 | ".to_string() + &config.title));
+    context.insert("need_pager", &true);
+    context.insert(
+        "page",
+        &Page::new(config.t("title_gallery") + " | " + &config.title),
+    );
 
-    let page = (pictures.len()-1) / 20 + 1;
+    let page = pictures.len().saturating_sub(1) / 20 + 1;
 
     for i in 1..=page {
         context.insert(
             "pics",
             &pictures[(i - 1) * 20..min((i) * 20, pictures.len())],
         );
-        context.insert("nav", &Navigator::new(page, i));
+        context.insert("pager", &Navigator::new(page, i));
 
         gen_html(
-            &context,
+            config,
+            &mut context,
             "picture.html",
-            &("public/picture/".to_string() + &i.to_string() + ".html"),
+            &("picture/".to_string() + &i.to_string() + ".html"),
         )?;
 
         URL_ENTRY.with(|v| {
             (*v.borrow_mut()).push(
                 UrlEntry::builder()
-                    .loc(config.url.clone() + &"/picture/".to_string() + &i.to_string() + ".html"),
+                    .loc(config.url.clone() + "/picture/" + &i.to_string() + ".html"),
             );
         });
     }
@@ -301,20 +363,18 @@ fn picture(pictures: Vec<PhotographyPictureBrief>) -> Result<(), err::Error> {
     Ok(())
 }
 
-fn sitemap() -> Result<(), err::Error> {
+fn sitemap(config: &GlobalConfig) -> Result<(), err::Error> {
     info!("Dealing With Sitemap");
-    let config = GlobalConfig::global();
-    if let None = config.robot {
+    if config.robot.is_none() {
         return Ok(());
     };
 
-    let mut robot = std::fs::File::create("public/robots.txt")?;
-    robot.write(
+    write_output(
+        "robots.txt",
         (String::from("Sitemap: ") + &config.url + "/" + config.robot.as_ref().unwrap()).as_bytes(),
     )?;
 
-    let mut site_map =
-        std::fs::File::create(PathBuf::from("public").join(config.robot.as_ref().unwrap()))?;
+    let mut site_map = Vec::new();
     let writer = SiteMapWriter::new(&mut site_map);
     let mut writer = writer.start_urlset()?;
     URL_ENTRY.with(|v| {
@@ -325,28 +385,44 @@ fn sitemap() -> Result<(), err::Error> {
         }
     });
     writer.end()?;
+    write_output(config.robot.as_ref().unwrap(), &site_map)?;
 
     info!("Sitemap Written");
     Ok(())
 }
 
-/// render all pages
+/// render all pages into root, a copy of the output folder
 /// sitemap is generated at the same time
+/// unchanged pages are not written again, and pages no longer generated are removed
+/// returns the manifest to save once root is in place
 pub fn render(
+    root: &Path,
     articles: Vec<Article>,
     pictures: Vec<PhotographyPictureBrief>,
-) -> Result<(), err::Error> {
+) -> Result<Manifest, err::Error> {
     info!("Rendering");
 
+    // the config may be reloaded meanwhile, so a single one is used for all pages
+    let config = GlobalConfig::global();
+
     URL_ENTRY.with(|v| *v.borrow_mut() = vec![]);
-    
-    index(articles.clone())?;
-    article_category(articles.clone())?;
-    gallery(pictures.clone())?;
-    picture(pictures.clone())?;
+    let conn = GlobalConnPool::global().0.get()?;
+    MANIFEST.with(|m| -> Result<(), err::Error> {
+        *m.borrow_mut() = Manifest::load(&conn, root, config.preview)?;
+        Ok(())
+    })?;
+
+    RENDER_CONFIG.with(|c| *c.borrow_mut() = Some(config.clone()));
+    index(&config, articles.clone())?;
+    article_category(&config, articles.clone())?;
+    gallery(&config, pictures.clone())?;
+    picture(&config, pictures.clone())?;
 
-    sitemap()?;
+    sitemap(&config)?;
+
+    let manifest = MANIFEST.with(|m| m.take());
+    manifest.prune()?;
 
     info!("Rendered");
-    Ok(())
+    Ok(manifest)
 }
@@ -0,0 +1,92 @@
+/*
+ * Uploading the queued pictures to the deploy target
+ */
+use crate::api::config::GlobalConfig;
+use crate::api::err;
+use crate::api::sync::GlobalConnPool;
+use crate::model::uploads::{
+    get_unfinished_uploads, mark_failed, mark_uploaded, queue_missing_uploads, UploadStatus,
+};
+use crate::publisher::deployer::{current_target, upload_picture};
+use log::{info, warn};
+use std::sync::Mutex;
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+/// how often the worker looks for queued pictures
+const UPLOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// the worker and the deploys drain the same queue, one at a time
+static QUEUE_LOCK: Mutex<()> = Mutex::new(());
+
+/// uploading the queued pictures to the target set in the config, and the failed ones again
+/// a target never uploaded to, e.g. a new one, gets all the registered pictures queued
+/// it stops at the first failure, as the target is likely unreachable
+/// returns how many pictures are left in the queue
+pub fn upload_queued() -> Result<usize, err::Error> {
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    drain()
+}
+
+/// see upload_queued, called with the queue locked
+fn drain() -> Result<usize, err::Error> {
+    let conn = GlobalConnPool::global().0.get()?;
+    let target = current_target(&conn, &GlobalConfig::global())?;
+    let queued = queue_missing_uploads(&conn, &target)?;
+    if queued > 0 {
+        info!("{} Picture(s) Queued for {}", queued, target);
+    }
+    let uploads = get_unfinished_uploads(&conn, &target)?;
+
+    for (i, upload) in uploads.iter().enumerate() {
+        if upload.status == UploadStatus::Failed {
+            info!(
+                "Uploading {:?} again after {} attempt(s), last error: {}",
+                upload.path,
+                upload.attempts,
+                upload.error.as_deref().unwrap_or_default()
+            );
+        }
+        if let Err(e) = upload_picture(&upload.path) {
+            warn!("Picture {:?} not uploaded. {}", upload.path, e);
+            mark_failed(&conn, &target, &upload.path, &e.message)?;
+            return Ok(uploads.len() - i);
+        }
+        mark_uploaded(&conn, &target, &upload.path)?;
+    }
+    Ok(0)
+}
+
+/// uploading all the queued pictures, and deploying if none of them is left
+/// so that the site never links to a picture not uploaded
+/// the queue stays locked while deploying, as the release may be copied from the folder of pictures
+pub fn flush<T>(deploy: impl FnOnce() -> Result<T, err::Error>) -> Result<T, err::Error> {
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    let left = drain()?;
+    if left > 0 {
+        return Err(err::Error::new(
+            err::Reason::Deploy,
+            format!("{} picture(s) not uploaded yet", left),
+        ));
+    }
+    deploy()
+}
+
+/// running f with the queue locked, so that no picture is uploaded meanwhile
+/// e.g. switching releases, as the folder of pictures may be moved into another one
+pub fn locked<T>(f: impl FnOnce() -> Result<T, err::Error>) -> Result<T, err::Error> {
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    f()
+}
+
+/// start the worker thread draining the queue
+pub fn start() {
+    thread::spawn(|| loop {
+        sleep(UPLOAD_INTERVAL);
+        match upload_queued() {
+            Ok(0) => {}
+            Ok(left) => info!("{} Picture(s) Left to Upload", left),
+            Err(e) => warn!("Error uploading pictures, {:?}", e),
+        }
+    });
+}
@@ -0,0 +1,171 @@
+/*
+ * Local preview server
+ * Serves the rendered site and the local pictures, and reloads the pages after each render
+ */
+use crate::api::{config::GlobalConfig, err, sync::Rendered};
+
+use log::{info, warn};
+use std::fs::read;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Request, Response, Server};
+
+/// the url prefix under which pictures in pic_local are served
+pub const PIC_PREFIX: &str = "/pic";
+
+const RELOAD_PATH: &str = "/__arce/reload";
+
+/// injected into every html page, reloading it once the site is rendered again
+const RELOAD_SCRIPT: &str = "<script>\
+    new EventSource(\"/__arce/reload\").onmessage = function () { location.reload(); };\
+    </script>";
+
+/// serve the output folder until the process is killed
+pub fn serve(addr: &str) -> Result<(), err::Error> {
+    let server = Server::http(addr).map_err(|e| {
+        err::Error::new(
+            err::Reason::Internal,
+            format!("Unable to listen on {}: {}", addr, e),
+        )
+    })?;
+    info!("Serving on http://{}", addr);
+    println!("Serving on http://{}", addr);
+
+    for request in server.incoming_requests() {
+        // reload requests are held open, so each request gets its own thread
+        thread::spawn(|| {
+            if let Err(e) = handle(request) {
+                warn!("Error serving request, {:?}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(request: Request) -> Result<(), err::Error> {
+    let url = request.url().split(['?', '#']).next().unwrap_or("/");
+    let url = percent_decode(url);
+    info!("GET {}", url);
+
+    if url == RELOAD_PATH {
+        return reload(request);
+    }
+    if url == "/" {
+        let location = Header::from_bytes("Location", "/index/1.html").unwrap();
+        return Ok(request.respond(Response::empty(302).with_header(location))?);
+    }
+
+    let file = match url.strip_prefix(PIC_PREFIX) {
+        Some(name) if name.starts_with('/') => local_path(&GlobalConfig::global().pic_local, name),
+        _ => local_path(&GlobalConfig::global().output_dir, &url),
+    };
+    let file = match file {
+        Some(f) if f.is_dir() => f.join("index.html"),
+        Some(f) => f,
+        None => return Ok(request.respond(Response::empty(400))?),
+    };
+
+    let mut body = match read(&file) {
+        Ok(body) => body,
+        Err(_) => {
+            return Ok(request.respond(Response::from_string("Not Found").with_status_code(404))?)
+        }
+    };
+
+    let content_type = content_type(&file);
+    if content_type.starts_with("text/html") {
+        body = inject_reload(body);
+    }
+    let header = Header::from_bytes("Content-Type", content_type).unwrap();
+    Ok(request.respond(Response::from_data(body).with_header(header))?)
+}
+
+/// hold the request open as an event stream, sending an event after each render
+fn reload(request: Request) -> Result<(), err::Error> {
+    let mut writer = request.into_writer();
+    writer.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+        Content-Type: text/event-stream\r\n\
+        Cache-Control: no-cache\r\n\
+        Connection: keep-alive\r\n\r\n",
+    )?;
+    writer.flush()?;
+
+    let rendered = Rendered::global();
+    let mut seen = rendered.get();
+    loop {
+        let now = rendered.wait(seen, Duration::from_secs(15));
+        if now == seen {
+            // keeping the connection alive, and finding out closed ones
+            writer.write_all(b": ping\n\n")?;
+        } else {
+            info!("Reloading preview pages");
+            writer.write_all(b"data: reload\n\n")?;
+            seen = now;
+        }
+        writer.flush()?;
+    }
+}
+
+/// joining an url path to a local root, refusing anything escaping the root
+fn local_path(root: &Path, url: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in Path::new(url.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => path.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn inject_reload(body: Vec<u8>) -> Vec<u8> {
+    let html = String::from_utf8_lossy(&body);
+    match html.rfind("</body>") {
+        Some(i) => (html[..i].to_string() + RELOAD_SCRIPT + &html[i..]).into_bytes(),
+        None => (html.to_string() + RELOAD_SCRIPT).into_bytes(),
+    }
+}
+
+fn content_type(p: &Path) -> &'static str {
+    let ext = p
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match ext.as_str() {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// decoding %XX sequences in urls, invalid ones are kept as they are
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}